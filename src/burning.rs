@@ -0,0 +1,275 @@
+use super::*;
+
+// Characters for the ascii picture of the burn order: the step modulo 36 for burnt cells, `UNBURNT` for the rest.
+const STEP_DIGITS: &[u8; 36] = b"0123456789abcdefghijklmnopqrstuvwxyz";
+pub const UNBURNT: char = '!';
+
+// Result of Dhar's burning algorithm on a finite grid, see `GraphSandpile::burning`.
+// The sink and cells outside the mask burn at step 0, and cells which never burn form the forbidden subconfiguration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Burning {
+	steps: Vec<Vec<Option<u64>>>,
+	mask: Option<Rc<Mask>>,
+	grid: Grid,	// The burnt sandpile.
+}
+
+impl fmt::Display for Burning {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		for (i, row) in self.steps.iter().enumerate() {
+			for (j, step) in row.iter().enumerate() {
+				if let Some(mask) = &self.mask && !mask[i][j] {
+					write!(f, "{MASKED}")?;
+					continue;
+				}
+				match step {
+					Some(step) => write!(f, "{}", STEP_DIGITS[(step % 36) as usize] as char)?,
+					None => write!(f, "{UNBURNT}")?,
+				}
+			}
+			writeln!(f)?;
+		}
+		Ok(())
+	}
+}
+
+impl Burning {
+	pub(super) fn new(steps: Vec<Vec<Option<u64>>>, mask: Option<Rc<Mask>>, grid: Grid) -> Burning {
+		Burning {steps, mask, grid}
+	}
+
+	pub fn steps(&self) -> &[Vec<Option<u64>>] {
+		&self.steps
+	}
+
+	pub fn is_recurrent(&self) -> bool {
+		self.steps.iter().flatten().all(Option::is_some)
+	}
+
+	// Cells which never burn. Each of them has fewer chips than neighbours among them.
+	pub fn forbidden(&self) -> Mask {
+		self.steps.iter().map(|row| row.iter().map(Option::is_none).collect()).collect()
+	}
+
+	// The sandpile restricted to the forbidden cells, in the format of the `ascii` output, other cells being `MASKED`.
+	pub fn forbidden_to_string(&self) -> String {
+		let mut s = String::new();
+		for (row, steps) in self.grid.iter().zip(&self.steps) {
+			for (el, step) in row.iter().zip(steps) {
+				s.push(if step.is_some() {MASKED} else {VIS[if *el < 8 {*el} else {8} as usize]});
+			}
+			s.push('\n');
+		}
+		s
+	}
+
+	// Burnt cells take colours from the palette by their step modulo its length, and the forbidden ones `forbidden_color`.
+	// Cells outside the mask are transparent.
+	pub fn png(&self, fname: &str, colors: &[[u8; 4]], forbidden_color: [u8; 4]) -> io::Result<()> {
+		let mut pixels = Vec::with_capacity(self.grid.len() * self.grid[0].len() * 4);
+		for (i, row) in self.steps.iter().enumerate() {
+			for (j, step) in row.iter().enumerate() {
+				pixels.extend_from_slice(&match step {
+					_ if self.mask.as_ref().is_some_and(|mask| !mask[i][j]) => [0; 4],
+					Some(step) => colors[(step % colors.len() as u64) as usize],
+					None => forbidden_color,
+				});
+			}
+		}
+		repng::encode(File::create(fname)?, self.grid[0].len() as u32, self.grid.len() as u32, &pixels)
+	}
+
+	// The forbidden subconfiguration drawn like a sandpile, with transparent burnt cells.
+	pub fn png_forbidden(&self, fname: &str, colors: &[[u8; 4]]) -> io::Result<()> {
+		png_masked(&self.grid, &self.forbidden(), fname, colors, [0; 4])
+	}
+}
+
+// Spanning tree of a finite grid directed to the sink: for every cell, the stencil offset (di, dj) along which it is joined
+// to its parent and the number k of the edge among the parallel ones along it, 0 unless the offset carries several chips.
+// None for the sink and cells outside the mask.
+pub type Tree = Vec<Vec<Option<(isize, isize, Cell)>>>;
+
+const TREE_COLOR: [u8; 4] = [0, 0, 0, 255];
+const SINK_COLOR: [u8; 4] = [192, 192, 192, 255];
+const BACKGROUND_COLOR: [u8; 4] = [255, 255, 255, 255];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpanningTree {
+	pub(super) grid_type: FiniteGridType,
+	pub(super) mask: Option<Rc<Mask>>,
+	pub(super) edges: Tree,
+}
+
+// One edge per line as `x y: dy dx` or `x y: dy dx k`, the coordinates being in the order of `read_list`
+// and the offset in the order of stencil files, followed by a comment with the parent.
+impl fmt::Display for SpanningTree {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		for (i, row) in self.edges.iter().enumerate() {
+			for (j, edge) in row.iter().enumerate() {
+				let Some((di, dj, k)) = *edge else {
+					continue;
+				};
+				write!(f, "{j} {i}: {di} {dj}")?;
+				if k > 0 {
+					write!(f, " {k}")?;
+				}
+				match self.parent((i, j), (di, dj)) {
+					Some((ti, tj)) => writeln!(f, "\t# -> {tj} {ti}")?,
+					None => writeln!(f, "\t# -> sink")?,
+				}
+			}
+		}
+		Ok(())
+	}
+}
+
+impl SpanningTree {
+	pub fn new(grid_type: FiniteGridType, mask: Option<Rc<Mask>>, edges: Tree) -> SpanningTree {
+		SpanningTree {grid_type, mask, edges}
+	}
+
+	pub fn edges(&self) -> &Tree {
+		&self.edges
+	}
+
+	// The cell which gets chips from (i, j) along the offset, None for the sink.
+	fn parent(&self, (i, j): (usize, usize), (di, dj): (isize, isize)) -> Option<(usize, usize)> {
+		let (x, y) = (self.edges[0].len(), self.edges.len());
+		let sink = if self.grid_type.sink_in_grid() {0} else {x*y};
+		match FiniteGridSandpile::offset_target(self.grid_type, self.mask.as_deref(), (x, y), (i, j), (di, dj)) {
+			v if v == sink => None,
+			v => Some((v / x, v % x)),
+		}
+	}
+
+	// Every cell becomes a `scale`×`scale` square, with a frame of sink squares around the grid if the sink is outside it.
+	// An edge is a segment between the centres of the squares, and an edge through a periodic side becomes two halves.
+	pub fn png(&self, fname: &str, scale: usize) -> io::Result<()> {
+		let (x, y) = (self.edges[0].len(), self.edges.len());
+		let (w, h) = ((x + 2) * scale, (y + 2) * scale);
+		let mut pixels = vec![0; w * h * 4];
+		let mut fill = |(pi, pj): (isize, isize), color: &[u8; 4]| {
+			if pi >= 0 && pj >= 0 && (pi as usize) < h && (pj as usize) < w {
+				let p = (pi as usize * w + pj as usize) * 4;
+				pixels[p..p+4].copy_from_slice(color);
+			}
+		};
+		let sink_in_grid = self.grid_type.sink_in_grid();
+		for i in 0..y + 2 {
+			for j in 0..x + 2 {
+				let frame = i == 0 || j == 0 || i == y + 1 || j == x + 1;
+				let sink = if frame {!sink_in_grid} else {
+					sink_in_grid && (i, j) == (1, 1) || self.mask.as_ref().is_some_and(|mask| !mask[i-1][j-1])
+				};
+				let color = if sink {&SINK_COLOR} else if frame {&[0; 4]} else {&BACKGROUND_COLOR};
+				for pi in i*scale..(i+1)*scale {
+					for pj in j*scale..(j+1)*scale {
+						fill((pi as isize, pj as isize), color);
+					}
+				}
+			}
+		}
+		let centre = |(i, j): (usize, usize)| (((i + 1) * scale + scale / 2) as isize, ((j + 1) * scale + scale / 2) as isize);
+		let thickness = (scale / 4).max(1) as isize;
+		let mut segment = |(ci, cj): (isize, isize), (di, dj): (isize, isize), length: isize| {
+			let steps = di.abs().max(dj.abs()) * length;
+			for t in 0..=steps {
+				let (pi, pj) = (ci + di * length * t / steps.max(1), cj + dj * length * t / steps.max(1));
+				for oi in 0..thickness {
+					for oj in 0..thickness {
+						fill((pi - thickness / 2 + oi, pj - thickness / 2 + oj), &TREE_COLOR);
+					}
+				}
+			}
+		};
+		let scale = scale as isize;
+		for (i, row) in self.edges.iter().enumerate() {
+			for (j, edge) in row.iter().enumerate() {
+				let Some((di, dj, _)) = *edge else {
+					continue;
+				};
+				let straight = (i as isize + di, j as isize + dj);
+				match self.grid_type.neighbour((y, x), (i, j), (di, dj)) {
+					Some((ti, tj)) if (ti as isize, tj as isize) != straight => {
+						segment(centre((i, j)), (di, dj), scale / 2);
+						segment(centre((ti, tj)), (-di, -dj), scale / 2);
+					},
+					_ => segment(centre((i, j)), (di, dj), scale),
+				}
+			}
+		}
+		repng::encode(File::create(fname)?, w as u32, h as u32, &pixels)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::collections::HashSet;
+
+	#[test]
+	fn burning() {
+		// Recurrent if and only if everything burns, compared with adding the burning configuration.
+		for (grid_type, neighbourhood, (x, y)) in [
+			(FiniteGridType::Rectangular, Neighbourhood::VonNeumann, (3, 2)),
+			(FiniteGridType::Toroidal, Neighbourhood::VonNeumann, (2, 3)),
+			(FiniteGridType::Rectangular, Neighbourhood::Moore, (2, 2)),
+		] {
+			let k = neighbourhood.neighbours();
+			for n in 0..k.pow((x*y) as u32) {
+				let grid = (0..y).map(|i| (0..x).map(|j| n / k.pow((i*x + j) as u32) % k).collect()).collect();
+				let s = GridSandpile::from_grid(GridType::Finite(grid_type), neighbourhood.clone(), grid).unwrap();
+				let a = FiniteGridSandpile::try_from(&s).unwrap();
+				assert_eq!(a.burning().unwrap().is_recurrent(), a.graph_sandpile().is_recurrent(), "{grid_type:?} {neighbourhood:?}\n{s}");
+			}
+		}
+		// The two zeros in the middle column never burn: each of them has fewer chips than neighbours among them.
+		let s = GridSandpile::from_grid(GridType::Finite(FiniteGridType::Rectangular), Neighbourhood::VonNeumann, vec![vec![3, 2, 3], vec![3, 0, 3], vec![3, 0, 3]]).unwrap();
+		let burning = FiniteGridSandpile::try_from(&s).unwrap().burning().unwrap();
+		assert_eq!(burning.to_string(), "121\n1!1\n1!1\n");
+		assert_eq!(burning.forbidden_to_string(), "xxx\nx x\nx x\n");
+		assert!(!burning.is_recurrent());
+		let s = GridSandpile::from_grid(GridType::Finite(FiniteGridType::Toroidal), Neighbourhood::VonNeumann, vec![vec![0, 3, 3], vec![3, 3, 3]]).unwrap();
+		assert_eq!(FiniteGridSandpile::try_from(&s).unwrap().burning().unwrap().to_string(), "011\n122\n");
+		// An asymmetric stencil gives a directed graph.
+		let s = GridSandpile::from_grid(GridType::Finite(FiniteGridType::Rectangular), Neighbourhood::custom(vec![(0, 1, 1)]).unwrap(), vec![vec![0, 0]]).unwrap();
+		assert!(FiniteGridSandpile::try_from(&s).unwrap().burning().is_err());
+	}
+
+	#[test]
+	fn spanning_tree() {
+		// A bijection between recurrent sandpiles and spanning trees, including ones with parallel edges.
+		for (grid_type, neighbourhood, (x, y)) in [
+			(FiniteGridType::Rectangular, Neighbourhood::VonNeumann, (3, 2)),
+			(FiniteGridType::Toroidal, Neighbourhood::VonNeumann, (3, 2)),
+			(FiniteGridType::Rectangular, Neighbourhood::Moore, (2, 2)),
+			(FiniteGridType::Rectangular, Neighbourhood::custom(vec![(0, 1, 2), (0, -1, 2), (1, 0, 1), (-1, 0, 1)]).unwrap(), (2, 2)),
+		] {
+			let k = neighbourhood.neighbours();
+			let mut trees = HashSet::new();
+			for n in 0..k.pow((x*y) as u32) {
+				let grid: Grid = (0..y).map(|i| (0..x).map(|j| n / k.pow((i*x + j) as u32) % k).collect()).collect();
+				if grid_type.sink_in_grid() && grid[0][0] != 0 {
+					continue;
+				}
+				let s = GridSandpile::from_grid(GridType::Finite(grid_type), neighbourhood.clone(), grid).unwrap();
+				let a = FiniteGridSandpile::try_from(&s).unwrap();
+				if !a.burning().unwrap().is_recurrent() {
+					assert!(a.spanning_tree().is_err());
+					continue;
+				}
+				let tree = a.spanning_tree().unwrap();
+				assert_eq!(FiniteGridSandpile::from_spanning_tree(neighbourhood.clone(), &tree).unwrap(), s, "{grid_type:?} {neighbourhood:?}\n{tree}");
+				trees.insert(tree.to_string());
+			}
+			assert_eq!(BigUint::from(trees.len()), FiniteGridSandpile::group_order(grid_type, neighbourhood.clone(), (x, y)), "{grid_type:?} {neighbourhood:?}");
+		}
+		// Both cells burn at once, and with 2 chips above the edge between them, each takes the third of its edges to the sink.
+		let s = GridSandpile::from_grid(GridType::Finite(FiniteGridType::Rectangular), Neighbourhood::VonNeumann, vec![vec![3, 3]]).unwrap();
+		let tree = FiniteGridSandpile::try_from(&s).unwrap().spanning_tree().unwrap();
+		assert_eq!(tree.to_string(), "0 0: 1 0\t# -> sink\n1 0: 0 1\t# -> sink\n");
+		// A cycle is not a tree.
+		let tree = SpanningTree::new(FiniteGridType::Rectangular, None, vec![vec![Some((0, 1, 0)), Some((0, -1, 0))]]);
+		assert!(FiniteGridSandpile::from_spanning_tree(Neighbourhood::VonNeumann, &tree).is_err());
+	}
+}
@@ -0,0 +1,773 @@
+use super::*;
+
+use std::rc::Rc;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Graph {
+	edges: Vec<Vec<(usize, Cell)>>,	// Outgoing edges with multiplicities, one list per vertex.
+	degrees: Vec<Cell>,
+	sink: usize,
+}
+
+impl Graph {
+	// Builds an undirected multigraph from an adjacency list: `adjacency[v]` lists pairs (u, m),
+	// meaning m edges between v and u, and every edge has to be listed at both its ends.
+	// Repeated pairs are summed up, (v, m) in `adjacency[v]` stands for m loops at v.
+	pub fn undirected(adjacency: Vec<Vec<(usize, Cell)>>, sink: usize) -> Result<Graph, SandpileError> {
+		let graph = Graph::new(adjacency, sink)?;
+		for (v, edges) in graph.edges.iter().enumerate() {
+			for &(u, m) in edges {
+				if graph.multiplicity(u, v) != m {
+					return Err(SandpileError::AsymmetricGraph(v, u));
+				}
+			}
+		}
+		graph.verify_sink_reachable()?;
+		Ok(graph)
+	}
+
+	// Builds a directed multigraph: `adjacency[v]` lists pairs (u, m), meaning m edges from v to u.
+	// Every vertex has to have a directed path to the sink.
+	pub fn directed(adjacency: Vec<Vec<(usize, Cell)>>, sink: usize) -> Result<Graph, SandpileError> {
+		let graph = Graph::new(adjacency, sink)?;
+		graph.verify_sink_reachable()?;
+		Ok(graph)
+	}
+
+	fn new(adjacency: Vec<Vec<(usize, Cell)>>, sink: usize) -> Result<Graph, SandpileError> {
+		let n = adjacency.len();
+		if n == 0 {
+			return Err(SandpileError::EmptyGrid);
+		}
+		if sink >= n {
+			return Err(SandpileError::InvalidVertex(sink));
+		}
+		let mut edges = Vec::with_capacity(n);
+		for mut list in adjacency {
+			list.retain(|&(_, m)| m > 0);
+			list.sort_unstable();
+			let mut merged: Vec<(usize, Cell)> = Vec::with_capacity(list.len());
+			for (u, m) in list {
+				if u >= n {
+					return Err(SandpileError::InvalidVertex(u));
+				}
+				match merged.last_mut() {
+					Some((lu, lm)) if *lu == u => *lm += m,
+					_ => merged.push((u, m)),
+				}
+			}
+			edges.push(merged);
+		}
+		let degrees = edges.iter().map(|list| list.iter().map(|&(_, m)| m).sum()).collect();
+		Ok(Graph {
+			edges,
+			degrees,
+			sink,
+		})
+	}
+
+	fn verify_sink_reachable(&self) -> Result<(), SandpileError> {
+		let n = self.edges.len();
+		let mut reverse = vec![Vec::new(); n];
+		for (v, edges) in self.edges.iter().enumerate() {
+			for &(u, _) in edges {
+				reverse[u].push(v);
+			}
+		}
+		let mut reached = vec![false; n];
+		reached[self.sink] = true;
+		let mut stack = vec![self.sink];
+		while let Some(u) = stack.pop() {
+			for &v in &reverse[u] {
+				if !reached[v] {
+					reached[v] = true;
+					stack.push(v);
+				}
+			}
+		}
+		match reached.iter().position(|&r| !r) {
+			Some(v) => Err(SandpileError::SinkUnreachable(v)),
+			None => Ok(()),
+		}
+	}
+
+	// Whether the graph is undirected but for the edges of the sink, which may go one way only, as on grids.
+	fn verify_undirected(&self) -> Result<(), SandpileError> {
+		for v in 0..self.len() {
+			for &(u, m) in &self.edges[v] {
+				if v != self.sink && u != self.sink && self.multiplicity(u, v) != m {
+					return Err(SandpileError::AsymmetricGraph(v, u));
+				}
+			}
+		}
+		Ok(())
+	}
+
+	pub fn multiplicity(&self, v: usize, u: usize) -> Cell {
+		match self.edges[v].binary_search_by_key(&u, |&(w, _)| w) {
+			Ok(k) => self.edges[v][k].1,
+			Err(_) => 0,
+		}
+	}
+
+	pub fn len(&self) -> usize {
+		self.edges.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.edges.is_empty()
+	}
+
+	pub fn sink(&self) -> usize {
+		self.sink
+	}
+
+	pub fn degree(&self, v: usize) -> Cell {
+		self.degrees[v]
+	}
+
+	pub fn edges(&self, v: usize) -> &[(usize, Cell)] {
+		&self.edges[v]
+	}
+
+	// The subgraph of the kept vertices, the other ones merged into the sink, which becomes the last vertex.
+	// The kept vertices must not have edges to the other ones but the sink. Returns the new number of every kept vertex too.
+	fn restrict(&self, keep: &[bool]) -> (Graph, Vec<usize>) {
+		let mut index = vec![usize::MAX; self.len()];
+		let mut n = 0;
+		for v in 0..self.len() {
+			if keep[v] {
+				index[v] = n;
+				n += 1;
+			}
+		}
+		let mut adjacency: Vec<_> = (0..self.len()).filter(|&v| keep[v])
+			.map(|v| self.edges[v].iter().map(|&(u, m)| (if keep[u] {index[u]} else {n}, m)).collect())
+			.collect();
+		adjacency.push(Vec::new());
+		(Graph::directed(adjacency, n).unwrap(), index)
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct GraphSandpile {
+	graph: Rc<Graph>,
+	config: Vec<Cell>,	// Chips on every vertex, the sink always has 0.
+	last_topple: u64,
+}
+
+impl PartialEq for GraphSandpile {
+	fn eq(&self, other: &GraphSandpile) -> bool {
+		(Rc::ptr_eq(&self.graph, &other.graph) || self.graph == other.graph) && self.config == other.config
+	}
+}
+
+impl Eq for GraphSandpile {}
+
+impl Hash for GraphSandpile {
+	fn hash<H: Hasher>(&self, state: &mut H) {
+		self.graph.hash(state);
+		self.config.hash(state);
+	}
+}
+
+impl GraphSandpile {
+	pub fn from_config(graph: Rc<Graph>, config: Vec<Cell>) -> Result<GraphSandpile, SandpileError> {
+		if config.len() != graph.len() {
+			return Err(SandpileError::UnequalLengths(graph.len(), config.len()));
+		}
+		let mut sandpile = GraphSandpile {
+			graph,
+			config,
+			last_topple: 0,
+		};
+		sandpile.topple();
+		Ok(sandpile)
+	}
+
+	pub fn add(&mut self, p: &GraphSandpile) -> Result<(), SandpileError> {
+		if !Rc::ptr_eq(&self.graph, &p.graph) && self.graph != p.graph {
+			return Err(SandpileError::UnequalGraphs);
+		}
+		self.add_config_unchecked(&p.config);
+		Ok(())
+	}
+
+	fn add_config_unchecked(&mut self, config: &[Cell]) {
+		for (el, &p) in self.config.iter_mut().zip(config) {
+			*el += p;
+		}
+		self.topple();
+	}
+
+	fn topple(&mut self) -> u64 {
+		let graph = Rc::clone(&self.graph);
+		self.config[graph.sink] = 0;
+		let mut excessive: Vec<_> = (0..graph.len())
+			.filter(|&v| v != graph.sink && self.config[v] >= graph.degrees[v])
+			.collect();
+		let mut ex2 = Vec::new();
+		let mut count = 0;
+		while !excessive.is_empty() {
+			for &v in &excessive {
+				let d = self.config[v] / graph.degrees[v];
+				if d == 0 {
+					continue;
+				}
+				self.config[v] %= graph.degrees[v];
+				count += d as u64;
+				for &(u, m) in &graph.edges[v] {
+					if u == graph.sink {
+						continue;
+					}
+					self.config[u] += d * m;
+					if self.config[u] >= graph.degrees[u] {
+						ex2.push(u);
+					}
+				}
+			}
+			(excessive, ex2) = (ex2, excessive);
+			ex2.clear();
+		}
+		self.last_topple = count;
+		count
+	}
+
+	// Proposition 6.36 of https://people.reed.edu/~davidp/divisors_and_sandpiles/
+	pub fn neutral(graph: Rc<Graph>) -> GraphSandpile {
+		let t: Vec<_> = (0..graph.len()).map(|v| 2 * graph.degree(v).saturating_sub(1)).collect();
+		let mut sandpile = GraphSandpile::from_config(graph, t.clone()).unwrap();
+		for (el, t) in sandpile.config.iter_mut().zip(t) {
+			*el = t - *el;
+		}
+		sandpile.topple();
+		sandpile
+	}
+
+	// Speer's burning configuration: b = L·s for the least script s with all s[v] ≥ 1 and b ≥ 0,
+	// L being the reduced Laplacian. For an undirected graph s = 1 and b is emitted by the sink firing once.
+	// In both cases a stable configuration c is recurrent if and only if c + b stabilizes to c.
+	pub fn burn(graph: Rc<Graph>) -> GraphSandpile {
+		let n = graph.len();
+		let mut incoming = vec![Vec::new(); n];
+		for v in 0..n {
+			for &(u, m) in graph.edges(v) {
+				if u != v && u != graph.sink && v != graph.sink {
+					incoming[u].push((v, m));
+				}
+			}
+		}
+		let lost = |v: usize| graph.degree(v) - graph.multiplicity(v, v);
+		let mut script = vec![1; n];
+		script[graph.sink] = 0;
+		let mut queue: Vec<_> = (0..n).filter(|&v| v != graph.sink).collect();
+		while let Some(v) = queue.pop() {
+			let gained: Cell = incoming[v].iter().map(|&(u, m)| m * script[u]).sum();
+			let need = gained.div_ceil(lost(v));
+			if need > script[v] {
+				script[v] = need;
+				for &(u, _) in graph.edges(v) {
+					if u != v && u != graph.sink {
+						queue.push(u);
+					}
+				}
+			}
+		}
+		let config = (0..n).map(|v| if v == graph.sink {0} else {
+			lost(v) * script[v] - incoming[v].iter().map(|&(u, m)| m * script[u]).sum::<Cell>()
+		}).collect();
+		GraphSandpile::from_config(graph, config).unwrap()
+	}
+
+	// Only meaningful for stable configurations, which all sandpiles built by this type are.
+	pub fn is_recurrent(&self) -> bool {
+		let mut b = GraphSandpile::burn(Rc::clone(&self.graph));
+		b.add_config_unchecked(&self.config);
+		b.config == self.config
+	}
+
+	// Dhar's burning algorithm: the fire starts at the sink, and a vertex catches it once it has at least as many chips
+	// as edges to the vertices which are not burnt yet, itself included. Returns the step at which every vertex burns,
+	// the sink burning at step 0. The vertices which never burn form a forbidden subconfiguration, so a stable sandpile
+	// is recurrent if and only if all of them burn. Only for undirected graphs, see `burn` for the general case.
+	pub fn burning(&self) -> Result<Vec<Option<u64>>, SandpileError> {
+		let graph = &self.graph;
+		let n = graph.len();
+		graph.verify_undirected()?;
+		let mut unburnt: Vec<Cell> = (0..n).map(|v| graph.degree(v) - graph.multiplicity(v, graph.sink)).collect();
+		let mut steps = vec![None; n];
+		steps[graph.sink] = Some(0);
+		let mut wave: Vec<_> = (0..n).filter(|&v| v != graph.sink && self.config[v] >= unburnt[v]).collect();
+		let mut step = 0;
+		while !wave.is_empty() {
+			step += 1;
+			for &v in &wave {
+				steps[v] = Some(step);
+			}
+			let mut next = Vec::new();
+			for &v in &wave {
+				for &(u, m) in graph.edges(v) {
+					if steps[u].is_none() {
+						// Every vertex joins the next wave only once, when it crosses the threshold.
+						let before = unburnt[u];
+						unburnt[u] -= m;
+						if self.config[u] >= unburnt[u] && self.config[u] < before {
+							next.push(u);
+						}
+					}
+				}
+			}
+			wave = next;
+		}
+		Ok(steps)
+	}
+
+	// The burning bijection between recurrent configurations and spanning trees directed to the sink.
+	// Every vertex burning at step k is joined to a vertex of step k-1 by one of the b edges between them: it has c chips
+	// and a edges to the vertices which have not burnt before step k, so a ≤ c < a + b, and the (c - a)-th edge is taken,
+	// edges ordered by the vertex they lead to. Returns the parent of every vertex and the number of the edge among
+	// the parallel ones, None for the sink. Only for undirected graphs.
+	pub fn spanning_tree(&self) -> Result<Vec<Option<(usize, Cell)>>, SandpileError> {
+		let steps = self.burning()?;
+		let Some(steps) = steps.into_iter().collect::<Option<Vec<_>>>() else {
+			return Err(SandpileError::NotRecurrent);
+		};
+		let graph = &self.graph;
+		Ok((0..graph.len()).map(|v| if v == graph.sink {None} else {
+			let a: Cell = graph.edges(v).iter().filter(|&&(u, _)| steps[u] >= steps[v]).map(|&(_, m)| m).sum();
+			let mut k = self.config[v] - a;
+			graph.edges(v).iter().filter(|&&(u, _)| steps[u] + 1 == steps[v]).find_map(|&(u, m)| {
+				if k < m {
+					Some((u, k))
+				} else {
+					k -= m;
+					None
+				}
+			})
+		}).collect())
+	}
+
+	// The inverse of `spanning_tree`: the depth of every vertex in the tree is its burning step.
+	#[allow(clippy::needless_range_loop)]
+	pub fn from_spanning_tree(graph: Rc<Graph>, parents: &[Option<(usize, Cell)>]) -> Result<GraphSandpile, SandpileError> {
+		let n = graph.len();
+		if parents.len() != n {
+			return Err(SandpileError::UnequalLengths(n, parents.len()));
+		}
+		graph.verify_undirected()?;
+		for v in 0..n {
+			match parents[v] {
+				None if v == graph.sink => {},
+				Some((u, k)) if v != graph.sink && u != v && u < n && k < graph.multiplicity(v, u) => {},
+				_ => return Err(SandpileError::InvalidTree(v)),
+			}
+		}
+		let mut depth = vec![None; n];
+		depth[graph.sink] = Some(0);
+		let mut path = Vec::new();
+		for v in 0..n {
+			let mut w = v;
+			while depth[w].is_none() {
+				if path.len() == n {
+					return Err(SandpileError::InvalidTree(v));
+				}
+				path.push(w);
+				w = parents[w].unwrap().0;
+			}
+			let mut d = depth[w].unwrap();
+			while let Some(u) = path.pop() {
+				d += 1;
+				depth[u] = Some(d);
+			}
+		}
+		let depth: Vec<u64> = depth.into_iter().map(Option::unwrap).collect();
+		let config = (0..n).map(|v| match parents[v] {
+			None => 0,
+			Some((parent, k)) => graph.edges(v).iter()
+				.filter(|&&(u, _)| depth[u] >= depth[v] || depth[u] + 1 == depth[v] && u < parent)
+				.map(|&(_, m)| m).sum::<Cell>() + k,
+		}).collect();
+		GraphSandpile::from_config(graph, config)
+	}
+
+	// A uniformly random recurrent configuration: a uniform spanning tree by Wilson's algorithm, mapped by the burning bijection.
+	// A random walk from every vertex goes on until it hits the tree, and the walk with its loops erased joins the tree.
+	// Only for undirected graphs.
+	pub fn random_recurrent(graph: Rc<Graph>, seed: u64) -> Result<GraphSandpile, SandpileError> {
+		graph.verify_undirected()?;
+		let mut rng = SplitMix64::new(seed);
+		let n = graph.len();
+		let mut in_tree = vec![false; n];
+		in_tree[graph.sink] = true;
+		let mut parents = vec![None; n];
+		for start in 0..n {
+			// Only the last exit from every vertex is remembered, which erases the loops.
+			let mut v = start;
+			while !in_tree[v] {
+				let mut r = rng.below(graph.degree(v));
+				parents[v] = graph.edges(v).iter().find_map(|&(u, m)| if r < m {Some((u, r))} else {r -= m; None});
+				v = parents[v].unwrap().0;
+			}
+			let mut v = start;
+			while !in_tree[v] {
+				in_tree[v] = true;
+				v = parents[v].unwrap().0;
+			}
+		}
+		GraphSandpile::from_spanning_tree(graph, &parents)
+	}
+
+	#[allow(clippy::needless_range_loop)]
+	pub fn inverse(&self) -> GraphSandpile {
+		let t: Vec<_> = (0..self.graph.len()).map(|v| 2 * self.graph.degree(v).saturating_sub(1)).collect();
+		let mut sandpile = GraphSandpile::from_config(Rc::clone(&self.graph), t.clone()).unwrap();
+		for v in 0..self.config.len() {
+			sandpile.config[v] = 2 * (t[v] - sandpile.config[v]) - self.config[v];
+		}
+		sandpile.topple();
+		sandpile
+	}
+
+	// The order in the sandpile group, see `Graph::element_order`.
+	pub fn order(&self) -> BigUint {
+		self.graph.element_order(&self.config)
+	}
+
+	// The multiples s, 2s, 3s, … of the sandpile are eventually periodic. Returns the length of the tail,
+	// that is, how many of them are not recurrent, and the length of the cycle, which is the order of s.
+	pub fn rho(&self) -> (u64, BigUint) {
+		(self.tail(), self.order())
+	}
+
+	// Vertices which the chips can't reach never change, so the recurrence is checked on the rest of the graph.
+	// Since recurrent plus anything is recurrent, the first recurrent multiple is found by doubling and then by binary search.
+	pub(super) fn tail(&self) -> u64 {
+		let n = self.graph.len();
+		let mut reached = vec![false; n];
+		let mut stack: Vec<_> = (0..n).filter(|&v| self.config[v] > 0).collect();
+		for &v in &stack {
+			reached[v] = true;
+		}
+		while let Some(v) = stack.pop() {
+			for &(u, _) in &self.graph.edges[v] {
+				if !reached[u] && u != self.graph.sink {
+					reached[u] = true;
+					stack.push(u);
+				}
+			}
+		}
+		let (graph, index) = self.graph.restrict(&reached);
+		let mut config = vec![0; graph.len()];
+		for v in (0..n).filter(|&v| reached[v]) {
+			config[index[v]] = self.config[v];
+		}
+		let s = GraphSandpile::from_config(Rc::new(graph), config).unwrap();
+		if s.is_recurrent() {
+			return 0
+		}
+		// Multiples 2^i·s, none of them recurrent.
+		let mut powers = vec![s];
+		loop {
+			let mut p = powers.last().unwrap().clone();
+			p.add_config_unchecked(&powers.last().unwrap().config);
+			if p.is_recurrent() {
+				break;
+			}
+			powers.push(p);
+		}
+		let mut current = powers.pop().unwrap();
+		let mut k = 1 << powers.len();
+		while let Some(p) = powers.pop() {
+			let mut next = current.clone();
+			next.add_config_unchecked(&p.config);
+			if !next.is_recurrent() {
+				current = next;
+				k += 1 << powers.len();
+			}
+		}
+		k
+	}
+
+	// Adds the sandpile to itself until it gets back, starting from the first recurrent multiple.
+	pub fn order_by_addition(&self) -> u64 {
+		let mut start = self.clone();
+		for _ in 0..self.tail() {
+			start.add_config_unchecked(&self.config);
+		}
+		let mut a = start.clone();
+		a.add_config_unchecked(&self.config);
+		let mut count = 1;
+		while a.config != start.config {
+			a.add_config_unchecked(&self.config);
+			count += 1;
+		}
+		count
+	}
+
+	pub fn chips_count(&self) -> u64 {
+		self.config.iter().map(|&x| x as u64).sum()
+	}
+
+	pub fn last_topple(&self) -> u64 {
+		self.last_topple
+	}
+
+	pub fn graph(&self) -> &Rc<Graph> {
+		&self.graph
+	}
+
+	pub fn config(&self) -> &[Cell] {
+		&self.config
+	}
+
+	pub fn into_config(self) -> Vec<Cell> {
+		self.config
+	}
+
+	// All recurrent configurations in the lexicographic order, see `AllRecurrent`.
+	pub fn all_recurrent(graph: Rc<Graph>) -> AllRecurrent {
+		let vertices = (0..graph.len()).filter(|&v| v != graph.sink).collect();
+		let config = (0..graph.len()).map(|v| if v == graph.sink {0} else {graph.degree(v) - 1}).collect();
+		AllRecurrent {
+			burn: GraphSandpile::burn(Rc::clone(&graph)),
+			undirected: graph.verify_undirected().is_ok(),
+			unburnt_at_start: (0..graph.len()).map(|u| graph.degree(u) - graph.multiplicity(u, graph.sink)).collect(),
+			unburnt: Vec::with_capacity(graph.len()),
+			burnt: Vec::with_capacity(graph.len()),
+			stack: Vec::with_capacity(graph.len()),
+			graph,
+			vertices,
+			config,
+			started: false,
+		}
+	}
+}
+
+// Recurrent configurations are closed upwards among the stable ones, so the first vertices are given chips one by one,
+// the others being full, and a branch is cut as soon as it is not recurrent even so. Every branch thus ends
+// with a recurrent configuration, and the time per configuration is polynomial in the size of the graph.
+pub struct AllRecurrent {
+	graph: Rc<Graph>,
+	burn: GraphSandpile,
+	undirected: bool,
+	vertices: Vec<usize>,	// All but the sink.
+	config: Vec<Cell>,
+	started: bool,
+	unburnt_at_start: Vec<Cell>,	// Edges to all vertices but the sink.
+	unburnt: Vec<Cell>,	// Buffers for `least`.
+	burnt: Vec<bool>,
+	stack: Vec<usize>,
+}
+
+impl AllRecurrent {
+	fn is_recurrent(&self) -> bool {
+		let mut b = self.burn.clone();
+		b.add_config_unchecked(&self.config);
+		b.config == self.config
+	}
+
+	// The least number of chips on v which keeps the configuration recurrent, if the full v is.
+	// On an undirected graph, the fire spreads everywhere but v and then needs as many chips on v as edges to the unburnt vertices.
+	fn least(&mut self, v: usize) -> Cell {
+		let max = self.graph.degree(v) - 1;
+		if !self.undirected {
+			self.config[v] = 0;
+			while self.config[v] < max && !self.is_recurrent() {
+				self.config[v] += 1;
+			}
+			return self.config[v]
+		}
+		let graph = &self.graph;
+		self.unburnt.clone_from(&self.unburnt_at_start);
+		self.burnt.clear();
+		self.burnt.extend((0..graph.len()).map(|u| u == graph.sink || u != v && self.config[u] >= self.unburnt[u]));
+		self.stack.clear();
+		self.stack.extend((0..graph.len()).filter(|&u| u != graph.sink && self.burnt[u]));
+		while let Some(w) = self.stack.pop() {
+			for &(u, m) in graph.edges(w) {
+				if !self.burnt[u] {
+					self.unburnt[u] -= m;
+					if u != v && self.config[u] >= self.unburnt[u] {
+						self.burnt[u] = true;
+						self.stack.push(u);
+					}
+				}
+			}
+		}
+		self.unburnt[v].min(max)
+	}
+
+	// The least numbers of chips on the vertices from `depth` on, one by one.
+	fn descend(&mut self, depth: usize) {
+		for d in depth..self.vertices.len() {
+			let v = self.vertices[d];
+			self.config[v] = self.least(v);
+		}
+	}
+}
+
+impl Iterator for AllRecurrent {
+	type Item = GraphSandpile;
+
+	fn next(&mut self) -> Option<GraphSandpile> {
+		if !self.started {
+			self.started = true;
+			self.descend(0);
+		} else {
+			// The last vertex which isn't full gets one more chip, and the vertices after it start anew.
+			let depth = (0..self.vertices.len()).rev().find(|&d| self.config[self.vertices[d]] + 1 < self.graph.degree(self.vertices[d]))?;
+			self.config[self.vertices[depth]] += 1;
+			for &v in &self.vertices[depth+1..] {
+				self.config[v] = self.graph.degree(v) - 1;
+			}
+			self.descend(depth + 1);
+		}
+		Some(GraphSandpile {
+			graph: Rc::clone(&self.graph),
+			config: self.config.clone(),
+			last_topple: 0,
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// Wheel with 4 spokes, the hub is the sink.
+	fn wheel() -> Rc<Graph> {
+		Rc::new(Graph::undirected(vec![
+			vec![(1, 1), (2, 1), (3, 1), (4, 1)],
+			vec![(0, 1), (2, 1), (4, 1)],
+			vec![(0, 1), (1, 1), (3, 1)],
+			vec![(0, 1), (2, 1), (4, 1)],
+			vec![(0, 1), (3, 1), (1, 1)],
+		], 0).unwrap())
+	}
+
+	#[test]
+	fn invalid_graphs() {
+		assert!(matches!(Graph::undirected(vec![vec![(1, 1)], vec![]], 0), Err(SandpileError::AsymmetricGraph(0, 1))));
+		assert!(matches!(Graph::undirected(vec![vec![(1, 1)], vec![(0, 1)], vec![]], 0), Err(SandpileError::SinkUnreachable(2))));
+		assert!(matches!(Graph::undirected(vec![vec![(2, 1)], vec![]], 0), Err(SandpileError::InvalidVertex(2))));
+	}
+
+	#[test]
+	fn multiple_edges() {
+		let g = Rc::new(Graph::undirected(vec![vec![(1, 1), (1, 1)], vec![(0, 2), (1, 1)]], 0).unwrap());
+		assert_eq!(g.degree(1), 3);
+		let s = GraphSandpile::from_config(Rc::clone(&g), vec![0, 7]).unwrap();
+		assert_eq!(s.config(), [0, 1]);
+		assert_eq!(s.last_topple(), 3);
+	}
+
+	#[test]
+	fn wheel_group() {
+		let g = wheel();
+		let e = GraphSandpile::neutral(Rc::clone(&g));
+		let mut s = GraphSandpile::from_config(Rc::clone(&g), vec![0, 2, 1, 2, 0]).unwrap();
+		let r = s.clone();
+		s.add(&e).unwrap();
+		assert_eq!(s, r);
+		let mut b = GraphSandpile::burn(Rc::clone(&g));
+		b.add(&r).unwrap();
+		assert_eq!(b, r);
+		let mut i = r.inverse();
+		i.add(&r).unwrap();
+		assert_eq!(i, e);
+		// The wheel with 4 spokes has 45 spanning trees.
+		let max = GraphSandpile::from_config(g, vec![0, 2, 2, 2, 2]).unwrap();
+		assert_eq!(45 % max.order_by_addition(), 0);
+		assert_eq!(max.order(), BigUint::from(max.order_by_addition()));
+	}
+
+	// Dhar–Ramaswamy-like directed pile: every vertex fires to the sink and to the next vertex of a cycle.
+	fn directed_cycle() -> Rc<Graph> {
+		Rc::new(Graph::directed(vec![
+			vec![],
+			vec![(2, 3), (0, 1)],
+			vec![(3, 1), (0, 1)],
+			vec![(1, 1), (0, 2)],
+		], 0).unwrap())
+	}
+
+	#[test]
+	fn directed_recurrence() {
+		let g = directed_cycle();
+		assert!(matches!(Graph::directed(vec![vec![], vec![(2, 1)], vec![(1, 1)]], 0), Err(SandpileError::SinkUnreachable(_))));
+		assert!(Graph::undirected(vec![vec![], vec![(2, 1)], vec![(0, 1)]], 0).is_err());
+		let e = GraphSandpile::neutral(Rc::clone(&g));
+		let mut recurrent = 0;
+		for c1 in 0..4 {
+			for c2 in 0..2 {
+				for c3 in 0..3 {
+					let s = GraphSandpile::from_config(Rc::clone(&g), vec![0, c1, c2, c3]).unwrap();
+					assert_eq!(s.last_topple(), 0);
+					let mut s2 = s.clone();
+					s2.add(&e).unwrap();
+					assert_eq!(s.is_recurrent(), s == s2);
+					if s.is_recurrent() {
+						recurrent += 1;
+					}
+				}
+			}
+		}
+		// Determinant of the reduced Laplacian [[4, 0, -1], [-3, 2, 0], [0, -1, 3]], the number of arborescences.
+		assert_eq!(recurrent, 21);
+		let b = GraphSandpile::burn(Rc::clone(&g));
+		assert_eq!(b.config(), [0, 3, 1, 1]);
+		assert!(e.is_recurrent());
+		let mut i = e.inverse();
+		i.add(&e).unwrap();
+		assert_eq!(i, e);
+	}
+
+	#[test]
+	fn rho() {
+		let g = directed_cycle();
+		for c1 in 0..4 {
+			for c2 in 0..2 {
+				for c3 in 0..3 {
+					let s = GraphSandpile::from_config(Rc::clone(&g), vec![0, c1, c2, c3]).unwrap();
+					let (tail, cycle) = s.rho();
+					assert_eq!(tail == 0, s.is_recurrent() || s.chips_count() == 0);
+					assert_eq!(cycle, BigUint::from(s.order_by_addition()));
+				}
+			}
+		}
+		// The tail is the number of non-recurrent multiples.
+		let g = Rc::new(FiniteGridSandpile::graph(FiniteGridType::Rectangular, Neighbourhood::VonNeumann, (3, 1)));
+		for c in 1..64 {
+			let s = GraphSandpile::from_config(Rc::clone(&g), vec![c % 4, c / 4 % 4, c / 16, 0]).unwrap();
+			let mut a = s.clone();
+			let mut tail = 0;
+			while !a.is_recurrent() && tail < 100 {
+				a.add(&s).unwrap();
+				tail += 1;
+			}
+			assert_eq!(s.tail(), tail);
+		}
+		// The chips never reach the vertex 1 from the vertex 2, so the recurrence is only needed on the vertices 2 and 3.
+		let path = Rc::new(Graph::directed(vec![vec![], vec![(2, 1), (0, 2)], vec![(3, 2)], vec![(0, 2)]], 0).unwrap());
+		let s = GraphSandpile::from_config(Rc::clone(&path), vec![0, 0, 1, 0]).unwrap();
+		assert_eq!(s.rho(), (0, BigUint::from(2u8)));
+		let s = GraphSandpile::from_config(path, vec![0; 4]).unwrap();
+		assert_eq!(s.rho(), (0, BigUint::from(1u8)));
+	}
+
+	#[test]
+	fn grid_graph() {
+		for grid_type in [FiniteGridType::Rectangular, FiniteGridType::Toroidal] {
+			for neighbourhood in [Neighbourhood::VonNeumann, Neighbourhood::Moore] {
+				let (x, y) = (5, 3);
+				let g = Rc::new(FiniteGridSandpile::graph(grid_type, neighbourhood.clone(), (x, y)));
+				let e = GraphSandpile::neutral(Rc::clone(&g));
+				let grid = FiniteGridSandpile::neutral(grid_type, neighbourhood.clone(), (x, y)).into_grid();
+				assert_eq!(&e.config()[..x*y], grid.concat());
+				let b = GraphSandpile::burn(g);
+				let grid = FiniteGridSandpile::burn(grid_type, neighbourhood, (x, y)).into_grid();
+				assert_eq!(&b.config()[..x*y], grid.concat());
+			}
+		}
+	}
+}
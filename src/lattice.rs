@@ -0,0 +1,481 @@
+use super::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LatticeType {
+	Finite,  	// Box with sink beyond every face.
+	Periodic,	// Periodic in every direction, with sink at the origin cell.
+	Infinite,	// Auto-extending lattice with no sink and no sandpile group.
+}
+
+// Sandpile on the d-dimensional cubic lattice, where every cell topples with 2d chips giving one to each nearest neighbour.
+// Cells are stored in a flat vector, the first coordinate (x) being the fastest one.
+#[derive(Debug, Clone)]
+pub struct LatticeSandpile {
+	lattice_type: LatticeType,
+	dimensions: Vec<usize>,
+	origin: Vec<usize>,	// Position of the origin within the stored box, which is nonzero only for the infinite lattice.
+	cells: Vec<Cell>,
+	last_topple: u64,
+}
+
+impl PartialEq for LatticeSandpile {
+	fn eq(&self, other: &LatticeSandpile) -> bool {
+		self.lattice_type == other.lattice_type && self.dimensions == other.dimensions && self.origin == other.origin && self.cells == other.cells
+	}
+}
+
+impl Eq for LatticeSandpile {}
+
+impl Hash for LatticeSandpile {
+	fn hash<H: Hasher>(&self, state: &mut H) {
+		self.lattice_type.hash(state);
+		self.dimensions.hash(state);
+		self.origin.hash(state);
+		self.cells.hash(state);
+	}
+}
+
+// Two-dimensional layers are separated by empty lines.
+impl fmt::Display for LatticeSandpile {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		for (n, layer) in self.layers().iter().enumerate() {
+			if n > 0 {
+				writeln!(f)?;
+			}
+			for row in layer {
+				for el in row {
+					write!(f, "{}", VIS[if *el < 8 {*el} else {8} as usize])?;
+				}
+				writeln!(f)?;
+			}
+		}
+		Ok(())
+	}
+}
+
+impl LatticeSandpile {
+	pub fn from_cells(lattice_type: LatticeType, dimensions: Vec<usize>, cells: Vec<Cell>) -> Result<LatticeSandpile, SandpileError> {
+		if dimensions.is_empty() || dimensions.contains(&0) {
+			return Err(SandpileError::EmptyGrid);
+		}
+		let n = dimensions.iter().product();
+		if cells.len() != n {
+			return Err(SandpileError::UnequalLengths(n, cells.len()));
+		}
+		let mut sandpile = LatticeSandpile {
+			lattice_type,
+			origin: vec![0; dimensions.len()],
+			dimensions,
+			cells,
+			last_topple: 0,
+		};
+		sandpile.topple();
+		Ok(sandpile)
+	}
+
+	pub fn neutral(lattice_type: LatticeType, dimensions: Vec<usize>) -> Result<LatticeSandpile, SandpileError> {
+		if lattice_type == LatticeType::Infinite {
+			return Err(SandpileError::Infinite);
+		}
+	// Proposition 6.36 of https://people.reed.edu/~davidp/divisors_and_sandpiles/
+		let t = 2 * (2 * dimensions.len() as Cell - 1);
+		let n = dimensions.iter().product();
+		let mut sandpile = LatticeSandpile::from_cells(lattice_type, dimensions, vec![t; n])?;
+		for el in &mut sandpile.cells {
+			*el = t - *el;
+		}
+		sandpile.topple();
+		Ok(sandpile)
+	}
+
+	// The configuration which the sink gives when it topples.
+	#[allow(clippy::needless_range_loop)]
+	pub fn burn(lattice_type: LatticeType, dimensions: Vec<usize>) -> Result<LatticeSandpile, SandpileError> {
+		let n = dimensions.iter().product();
+		let mut cells = vec![0; n];
+		match lattice_type {
+			LatticeType::Infinite => return Err(SandpileError::Infinite),
+			LatticeType::Finite => {
+				let strides = Self::strides(&dimensions);
+				for v in 0..n {
+					for k in 0..dimensions.len() {
+						let c = v / strides[k] % dimensions[k];
+						cells[v] += (c == 0) as Cell + (c == dimensions[k]-1) as Cell;
+					}
+				}
+			},
+			LatticeType::Periodic => {
+				let strides = Self::strides(&dimensions);
+				for k in 0..dimensions.len() {
+					for u in [1 % dimensions[k], dimensions[k]-1] {
+						cells[u * strides[k]] += 1;
+					}
+				}
+			},
+		}
+		LatticeSandpile::from_cells(lattice_type, dimensions, cells)
+	}
+
+	pub fn inverse(&self) -> Result<LatticeSandpile, SandpileError> {
+		let t = 2 * (2 * self.dimensions.len() as Cell - 1);
+		if self.lattice_type == LatticeType::Infinite {
+			return Err(SandpileError::Infinite);
+		}
+		let mut sandpile = LatticeSandpile::from_cells(self.lattice_type, self.dimensions.clone(), vec![t; self.cells.len()])?;
+		for v in 0..self.cells.len() {
+			sandpile.cells[v] = 2 * (t - sandpile.cells[v]) - self.cells[v];
+		}
+		sandpile.topple();
+		Ok(sandpile)
+	}
+
+	// The order in the sandpile group, see `Graph::element_order`, so the sandpile needn't be recurrent.
+	pub fn order(&self) -> Result<BigUint, SandpileError> {
+		if self.lattice_type == LatticeType::Infinite {
+			return Err(SandpileError::Infinite);
+		}
+		let graph = self.graph();
+		let mut config = self.cells.clone();
+		config.resize(graph.len(), 0);
+		Ok(graph.element_order(&config))
+	}
+
+	// The sink is the origin cell of the periodic lattice, and an extra vertex beyond the faces of the box.
+	fn graph(&self) -> Graph {
+		let n = self.cells.len();
+		let sink = if self.lattice_type == LatticeType::Periodic {0} else {n};
+		let mut adjacency = vec![Vec::new(); if sink == n {n+1} else {n}];
+		let strides = Self::strides(&self.dimensions);
+		for v in (0..n).filter(|&v| v != sink) {
+			for (&m, &stride) in self.dimensions.iter().zip(&strides) {
+				let c = v / stride % m;
+				let base = v - c * stride;
+				let targets = match self.lattice_type {
+					LatticeType::Periodic => [Some((c + m-1) % m), Some((c + 1) % m)],
+					_ => [c.checked_sub(1), Some(c + 1).filter(|&c| c < m)],
+				};
+				for u in targets {
+					let u = u.map_or(sink, |u| base + u * stride);
+					adjacency[v].push((u, 1));
+					if u == sink && sink == n {
+						adjacency[sink].push((v, 1));
+					}
+				}
+			}
+		}
+		Graph::directed(adjacency, sink).unwrap()
+	}
+
+	pub fn add(&mut self, p: &LatticeSandpile) -> Result<(), SandpileError> {
+		if self.lattice_type != p.lattice_type || self.dimensions.len() != p.dimensions.len() {
+			return Err(SandpileError::UnequalLattices);
+		}
+		if self.lattice_type == LatticeType::Infinite {
+			let d = self.dimensions.len();
+			let origin: Vec<_> = (0..d).map(|k| self.origin[k].max(p.origin[k])).collect();
+			let dimensions = (0..d).map(|k| (origin[k] - self.origin[k] + self.dimensions[k]).max(origin[k] - p.origin[k] + p.dimensions[k])).collect();
+			self.reframe(origin, dimensions);
+			let strides = Self::strides(&self.dimensions);
+			let pstrides = Self::strides(&p.dimensions);
+			for v in 0..p.cells.len() {
+				let mut u = 0;
+				for k in 0..d {
+					u += (v / pstrides[k] % p.dimensions[k] + self.origin[k] - p.origin[k]) * strides[k];
+				}
+				self.cells[u] += p.cells[v];
+			}
+			self.topple();
+			return Ok(())
+		}
+		if self.dimensions != p.dimensions {
+			return Err(SandpileError::UnequalLattices);
+		}
+		self.add_cells_unchecked(&p.cells);
+		Ok(())
+	}
+
+	// k·s by doubling and adding, see `GridSandpile::multiple`.
+	pub fn multiple(&self, mut k: u128) -> LatticeSandpile {
+		let mut result: Option<LatticeSandpile> = None;
+		let mut power = self.clone();
+		let mut topplings = 0;
+		while k > 0 {
+			if k & 1 == 1 {
+				result = Some(match result {
+					Some(mut result) => {
+						result.add(&power).unwrap();
+						topplings += result.last_topple;
+						result
+					},
+					None => power.clone(),
+				});
+			}
+			k >>= 1;
+			if k > 0 {
+				let p = power.clone();
+				power.add(&p).unwrap();
+				topplings += power.last_topple;
+			}
+		}
+		let mut result = result.unwrap_or_else(|| LatticeSandpile {cells: vec![0; self.cells.len()], ..self.clone()});
+		result.last_topple = topplings;
+		result
+	}
+
+	#[allow(clippy::needless_range_loop)]
+	fn add_cells_unchecked(&mut self, cells: &[Cell]) {
+		for v in 0..self.cells.len() {
+			self.cells[v] += cells[v];
+		}
+		self.topple();
+	}
+
+	fn strides(dimensions: &[usize]) -> Vec<usize> {
+		let mut strides = Vec::with_capacity(dimensions.len());
+		let mut s = 1;
+		for &n in dimensions {
+			strides.push(s);
+			s *= n;
+		}
+		strides
+	}
+
+	// Moves the cells into a larger box, keeping the origin at the given position.
+	fn reframe(&mut self, origin: Vec<usize>, dimensions: Vec<usize>) {
+		let d = self.dimensions.len();
+		let strides = Self::strides(&self.dimensions);
+		let new_strides = Self::strides(&dimensions);
+		let mut cells = vec![0; dimensions.iter().product()];
+		for v in 0..self.cells.len() {
+			let mut u = 0;
+			for k in 0..d {
+				u += (v / strides[k] % self.dimensions[k] + origin[k] - self.origin[k]) * new_strides[k];
+			}
+			cells[u] = self.cells[v];
+		}
+		self.cells = cells;
+		self.dimensions = dimensions;
+		self.origin = origin;
+	}
+
+	#[allow(clippy::needless_range_loop)]
+	fn topple(&mut self) -> u64 {
+		let d = self.dimensions.len();
+		let threshold = 2 * d as Cell;
+		if self.lattice_type == LatticeType::Periodic {
+			self.cells[0] = 0;
+		}
+		let mut excessive: Vec<_> = (0..self.cells.len()).filter(|&v| self.cells[v] >= threshold).collect();
+		let mut ex2 = Vec::new();
+		let mut strides = Self::strides(&self.dimensions);
+		let mut count = 0;
+		// The infinite box grows at least twice at a time, and in the end keeps the former cells and the neighbours
+		// of the toppled ones: coordinates from and to.
+		let mut kept: Vec<_> = self.dimensions.iter().map(|&n| (0, n-1)).collect();
+		while !excessive.is_empty() {
+			if self.lattice_type == LatticeType::Infinite {
+				// Cells on a face would send chips beyond the stored box, so it grows there.
+				let (mut lower, mut upper) = (vec![0; d], vec![0; d]);
+				for &v in &excessive {
+					for k in 0..d {
+						let c = v / strides[k] % self.dimensions[k];
+						if c == 0 {
+							lower[k] = self.dimensions[k];
+						}
+						if c == self.dimensions[k]-1 {
+							upper[k] = self.dimensions[k];
+						}
+					}
+				}
+				if lower.iter().chain(&upper).any(|&g| g > 0) {
+					let origin = (0..d).map(|k| self.origin[k] + lower[k]).collect();
+					let dimensions = (0..d).map(|k| self.dimensions[k] + lower[k] + upper[k]).collect();
+					let old = (self.dimensions.clone(), strides);
+					self.reframe(origin, dimensions);
+					strides = Self::strides(&self.dimensions);
+					for v in excessive.iter_mut() {
+						let mut u = 0;
+						for k in 0..d {
+							u += (*v / old.1[k] % old.0[k] + lower[k]) * strides[k];
+						}
+						*v = u;
+					}
+					for (k, (lo, hi)) in kept.iter_mut().enumerate() {
+						(*lo, *hi) = (*lo + lower[k], *hi + lower[k]);
+					}
+				}
+			}
+			for &v in &excessive {
+				let t = self.cells[v] / threshold;
+				if t == 0 {
+					continue;
+				}
+				self.cells[v] %= threshold;
+				count += t as u64;
+				for k in 0..d {
+					let n = self.dimensions[k];
+					let c = v / strides[k] % n;
+					let base = v - c * strides[k];
+					if self.lattice_type == LatticeType::Infinite {
+						kept[k] = (kept[k].0.min(c - 1), kept[k].1.max(c + 1));
+					}
+					let targets = match self.lattice_type {
+						LatticeType::Periodic => [Some((c + n-1) % n), Some((c + 1) % n)],
+						_ => [c.checked_sub(1), Some(c + 1).filter(|&c| c < n)],
+					};
+					for u in targets.into_iter().flatten() {
+						let u = base + u * strides[k];
+						if self.lattice_type == LatticeType::Periodic && u == 0 {
+							continue;
+						}
+						self.cells[u] += t;
+						if self.cells[u] >= threshold {
+							ex2.push(u);
+						}
+					}
+				}
+			}
+			(excessive, ex2) = (ex2, excessive);
+			ex2.clear();
+		}
+		if self.lattice_type == LatticeType::Infinite && kept.iter().zip(&self.dimensions).any(|(&(lo, hi), &n)| lo > 0 || hi < n-1) {
+			self.crop(&kept);
+		}
+		self.last_topple = count;
+		count
+	}
+
+	// Keeps the cells with the given coordinates, from and to, along every axis.
+	fn crop(&mut self, kept: &[(usize, usize)]) {
+		let dimensions: Vec<_> = kept.iter().map(|&(lo, hi)| hi - lo + 1).collect();
+		let strides = Self::strides(&self.dimensions);
+		let new_strides = Self::strides(&dimensions);
+		let mut cells = vec![0; dimensions.iter().product()];
+		for (u, cell) in cells.iter_mut().enumerate() {
+			let v: usize = kept.iter().enumerate().map(|(k, &(lo, _))| (u / new_strides[k] % dimensions[k] + lo) * strides[k]).sum();
+			*cell = self.cells[v];
+		}
+		self.origin = self.origin.iter().zip(kept).map(|(&o, &(lo, _))| o - lo).collect();
+		self.cells = cells;
+		self.dimensions = dimensions;
+	}
+
+	// Two-dimensional slices along the first two coordinates, the rest of them running in the same order as in the storage.
+	// A one-dimensional lattice is a single row.
+	pub fn layers(&self) -> Vec<Grid> {
+		let x = self.dimensions[0];
+		let y = if self.dimensions.len() > 1 {self.dimensions[1]} else {1};
+		self.cells.chunks(x*y).map(|layer| layer.chunks(x).map(|row| row.to_vec()).collect()).collect()
+	}
+
+	pub fn chips_count(&self) -> u64 {
+		self.cells.iter().map(|&x| x as u64).sum()
+	}
+
+	pub fn last_topple(&self) -> u64 {
+		self.last_topple
+	}
+
+	pub fn lattice_type(&self) -> LatticeType {
+		self.lattice_type
+	}
+
+	pub fn dimensions(&self) -> &[usize] {
+		&self.dimensions
+	}
+
+	pub fn origin(&self) -> &[usize] {
+		&self.origin
+	}
+
+	pub fn cells(&self) -> &[Cell] {
+		&self.cells
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn two_dimensional() {
+		for (lattice_type, grid_type) in [(LatticeType::Finite, FiniteGridType::Rectangular), (LatticeType::Periodic, FiniteGridType::Toroidal)] {
+			let e = LatticeSandpile::neutral(lattice_type, vec![5, 3]).unwrap();
+			assert_eq!(e.layers(), [FiniteGridSandpile::neutral(grid_type, Neighbourhood::VonNeumann, (5, 3)).into_grid()]);
+			let b = LatticeSandpile::burn(lattice_type, vec![5, 3]).unwrap();
+			assert_eq!(b.layers(), [FiniteGridSandpile::burn(grid_type, Neighbourhood::VonNeumann, (5, 3)).into_grid()]);
+		}
+		let mut a = LatticeSandpile::from_cells(LatticeType::Infinite, vec![1, 1], vec![100]).unwrap();
+		let g = GridSandpile::from_grid(GridType::Infinite(0, 0), Neighbourhood::VonNeumann, vec![vec![100]]).unwrap();
+		assert_eq!(a.layers(), [g.clone().into_grid()]);
+		assert_eq!(a.last_topple(), g.last_topple());
+		let GridType::Infinite(oy, ox) = g.grid_type() else { panic!() };
+		assert_eq!(a.origin(), [ox, oy]);
+		a.add(&LatticeSandpile::from_cells(LatticeType::Infinite, vec![1, 1], vec![100]).unwrap()).unwrap();
+		let mut g2 = g.clone();
+		g2.add(&g).unwrap();
+		assert_eq!(a.layers(), [g2.into_grid()]);
+	}
+
+	#[test]
+	fn three_dimensional() {
+		for lattice_type in [LatticeType::Finite, LatticeType::Periodic] {
+			let e = LatticeSandpile::neutral(lattice_type, vec![4, 3, 2]).unwrap();
+			let mut e2 = e.clone();
+			e2.add(&e).unwrap();
+			assert_eq!(e, e2);
+			let mut b = LatticeSandpile::burn(lattice_type, vec![4, 3, 2]).unwrap();
+			b.add(&e).unwrap();
+			assert_eq!(b, e);
+			let a = LatticeSandpile::from_cells(lattice_type, vec![4, 3, 2], vec![5; 24]).unwrap();
+			let mut i = a.inverse().unwrap();
+			i.add(&a).unwrap();
+			assert_eq!(i, e);
+			assert_eq!(e.order().unwrap(), BigUint::from(1u32));
+		}
+		// The 2×1×1 box is a single edge between two vertices with five edges each to the sink.
+		let b = LatticeSandpile::burn(LatticeType::Finite, vec![2, 1, 1]).unwrap();
+		assert_eq!(b.cells(), [5, 5]);
+		assert_eq!(LatticeSandpile::from_cells(LatticeType::Finite, vec![2, 1, 1], vec![5, 4]).unwrap().order().unwrap(), BigUint::from(35u32));
+		// Stable heights of 5 dimensions go beyond the palette of the png layers.
+		let a = LatticeSandpile::from_cells(LatticeType::Finite, vec![2; 5], vec![9; 32]).unwrap();
+		let colors: Vec<[u8; 4]> = (0..8).map(|k| [k * 30, 0, 0, 255]).collect();
+		let fname = std::env::temp_dir().join("sandpile-png-five-dimensional.png");
+		for layer in a.layers() {
+			png(&layer, fname.to_str().unwrap(), &colors).unwrap();
+		}
+		std::fs::remove_file(fname).unwrap();
+		// The order needn't be that of a recurrent sandpile: o·a is then equivalent to zero, so adding it to the identity
+		// gives the identity.
+		let a = LatticeSandpile::from_cells(LatticeType::Finite, vec![3, 3, 3], vec![1; 27]).unwrap();
+		let o = u128::try_from(a.order().unwrap()).unwrap();
+		let e = LatticeSandpile::neutral(LatticeType::Finite, vec![3, 3, 3]).unwrap();
+		let mut b = a.multiple(o);
+		b.add(&e).unwrap();
+		assert_eq!(b, e);
+		let mut b = a.multiple(o / 2);
+		b.add(&e).unwrap();
+		assert_ne!(b, e);
+		// On a single layer, it is that of the grid.
+		for (lattice_type, grid_type) in [(LatticeType::Finite, FiniteGridType::Rectangular), (LatticeType::Periodic, FiniteGridType::Toroidal)] {
+			let a = LatticeSandpile::from_cells(lattice_type, vec![4, 3], vec![1; 12]).unwrap();
+			let g = GridSandpile::from_grid(GridType::Finite(grid_type), Neighbourhood::VonNeumann, vec![vec![1; 4]; 3]).unwrap();
+			assert_eq!(a.order().unwrap(), FiniteGridSandpile::try_from(&g).unwrap().order());
+		}
+		// The single-source pile is symmetric under the permutations of the axes.
+		let a = LatticeSandpile::from_cells(LatticeType::Infinite, vec![1, 1, 1], vec![1000]).unwrap();
+		let n = a.dimensions()[0];
+		assert_eq!(a.dimensions(), [n, n, n]);
+		assert_eq!(a.origin(), [n/2, n/2, n/2]);
+		assert_eq!(a.chips_count(), 1000);
+		for i in 0..n {
+			for j in 0..n {
+				for k in 0..n {
+					assert!(a.cells()[i + n*j + n*n*k] < 6);
+					assert_eq!(a.cells()[i + n*j + n*n*k], a.cells()[j + n*k + n*n*i]);
+					assert_eq!(a.cells()[i + n*j + n*n*k], a.cells()[j + n*i + n*n*k]);
+				}
+			}
+		}
+	}
+}
@@ -0,0 +1,238 @@
+use super::*;
+
+// Least action principle: the odometer u of the stabilization is the least v >= 0 such that firing v leaves a stable
+// configuration, and if v <= u is fired first, the ordinary toppling of the rest takes exactly u - v more topplings
+// and ends in the same configuration.
+// A lower bound comes from the comparison principle: if every cell with v > 0 keeps at least threshold - 1 chips after
+// firing v, then v <= u. The candidate is the relaxed odometer, the least real w >= 0 with s + Δw <= threshold - 1,
+// found by projected Gauss–Seidel on a pyramid of coarser grids, and it is rounded and lowered until it is such a bound.
+// This pays off when every cell has many chips, so the infinite grid is deliberately left out: around a pile, where
+// most cells have few, the rounded relaxed odometer is far from such a bound, and the symmetric toppling is faster.
+
+// Chips per cell from which the least action is tried: on a lighter configuration, the bound saves little.
+const MIN_CHIPS_PER_CELL: Cell = 2;
+
+impl GridSandpile {
+	// Fires a lower bound of the odometer for the von Neumann and Moore neighbourhoods on the rectangle without a mask.
+	// Returns it, or None if nothing was fired.
+	pub(super) fn least_action_fire(&mut self) -> Option<Grid> {
+		let moore = match self.neighbourhood {
+			Neighbourhood::VonNeumann => false,
+			Neighbourhood::Moore => true,
+			_ => return None,
+		};
+		if self.mask.is_some() || self.grid_type != GridType::Finite(FiniteGridType::Rectangular) {
+			return None
+		}
+		let threshold = self.neighbourhood.neighbours();
+		// Beyond 2^96 chips, the floating point odometer would be too coarse.
+		let chips = self.grid.iter().flatten().fold(0 as Cell, |sum, &n| sum.saturating_add(n));
+		if chips < MIN_CHIPS_PER_CELL * threshold * (self.grid.len() * self.grid[0].len()) as Cell || chips >= 1 << 96 {
+			return None
+		}
+		let (h, w) = (self.grid.len(), self.grid[0].len());
+		let s: Vec<Vec<f64>> = self.grid.iter().map(|row| row.iter().map(|&n| n as f64).collect()).collect();
+		let relaxed = relaxed_odometer(&s, moore, threshold as f64);
+		// Rounded down, the candidate may still give some cells fewer than threshold - 1 chips, and these cells are lowered
+		// to the most they can fire, which may break their neighbours in turn.
+		let mut v: Vec<Vec<i128>> = relaxed.iter().map(|row| row.iter().map(|&x| x.floor() as i128).collect()).collect();
+		let offsets = self.neighbourhood.offsets((0, 0));
+		let neighbours = |(i, j): (usize, usize)| offsets.iter().filter_map(move |&(di, dj, _)| {
+			let (ti, tj) = (i as isize + di, j as isize + dj);
+			(ti >= 0 && tj >= 0 && (ti as usize) < h && (tj as usize) < w).then_some((ti as usize, tj as usize))
+		});
+		let (threshold, reserve) = (threshold as i128, threshold as i128 - 1);
+		let mut broken: Vec<_> = (0..h).flat_map(|i| (0..w).map(move |j| (i, j))).filter(|&(i, j)| v[i][j] > 0).collect();
+		let mut queued = vec![vec![true; w]; h];
+		while let Some((i, j)) = broken.pop() {
+			queued[i][j] = false;
+			if v[i][j] == 0 {
+				continue;
+			}
+			let gained = self.grid[i][j] as i128 + neighbours((i, j)).map(|(ti, tj)| v[ti][tj]).sum::<i128>();
+			if threshold * v[i][j] + reserve <= gained {
+				continue;
+			}
+			v[i][j] = (gained - reserve).div_euclid(threshold).max(0);
+			for (ti, tj) in neighbours((i, j)) {
+				if v[ti][tj] > 0 && !queued[ti][tj] {
+					queued[ti][tj] = true;
+					broken.push((ti, tj));
+				}
+			}
+		}
+		// Every cell keeps a nonnegative number of chips once it has got those of its neighbours.
+		let mut fired = vec![vec![0; w]; h];
+		for i in 0..h {
+			for j in 0..w {
+				fired[i][j] = v[i][j] as Cell;
+				for (ti, tj) in neighbours((i, j)) {
+					self.grid[ti][tj] += fired[i][j];
+				}
+			}
+		}
+		for (row, fired_row) in self.grid.iter_mut().zip(&fired) {
+			for (n, &k) in row.iter_mut().zip(fired_row) {
+				*n -= threshold as Cell * k;
+			}
+		}
+		Some(fired)
+	}
+}
+
+// The relaxed odometer solves A·w >= f, w >= 0, with equality where w > 0, for A·w = threshold·w - Σ w(neighbours)
+// and f = s - (threshold - 1). It is found by the full multigrid: the problem is solved on the coarsest grid, and
+// on every finer one, V-cycles of projected Gauss–Seidel sweeps and corrections from the coarser grids improve
+// the interpolated solution. A cell of the grid 2^k times coarser stands for a 2^k×2^k block and sums its right-hand
+// side, and its von Neumann Laplacian stands for the fine one, which is 3 times larger for the Moore neighbourhood.
+fn relaxed_odometer(s: &[Vec<f64>], moore: bool, threshold: f64) -> Vec<Vec<f64>> {
+	let mut pyramid = vec![Level {
+		h: s.len(),
+		w: s[0].len(),
+		moore,
+		f: s.iter().flatten().map(|&x| x - (threshold - 1.0)).collect(),
+	}];
+	while let Some(last) = pyramid.last() && last.h.max(last.w) > COARSEST {
+		let f = last.restrict(&last.f);
+		pyramid.push(Level {h: last.h.div_ceil(2), w: last.w.div_ceil(2), moore: false, f});
+	}
+	let coarsest = &pyramid[pyramid.len()-1];
+	let mut u = vec![0.0; coarsest.h * coarsest.w];
+	for _ in 0..COARSEST_SWEEPS {
+		if coarsest.smooth(&mut u, &coarsest.f, true) <= PRECISION {
+			break;
+		}
+	}
+	for k in (0..pyramid.len()-1).rev() {
+		u = pyramid[k+1].prolong(&u, (pyramid[k].h, pyramid[k].w));
+		for cycle in 0.. {
+			let change = v_cycle(&pyramid[k..], &mut u, &pyramid[k].f, true);
+			if cycle + 1 >= if k == 0 {MAX_CYCLES} else {CYCLES} || change <= PRECISION {
+				break;
+			}
+		}
+	}
+	u.chunks(s[0].len()).map(|row| row.to_vec()).collect()
+}
+
+// Sizes of the coarsest grid.
+const COARSEST: usize = 16;
+const COARSEST_SWEEPS: usize = 100000;
+// V-cycles on the grids coarser than the original one, and at most on it.
+const CYCLES: usize = 2;
+const MAX_CYCLES: usize = 50;
+// Largest change of a cell, in chips, at which the iterations stop.
+const PRECISION: f64 = 0.01;
+
+struct Level {
+	h: usize,
+	w: usize,
+	moore: bool,
+	f: Vec<f64>,
+}
+
+impl Level {
+	// A Gauss–Seidel sweep for A·u = f, projected to u >= 0 if asked. Returns the largest change.
+	fn smooth(&self, u: &mut [f64], f: &[f64], projected: bool) -> f64 {
+		let (h, w) = (self.h, self.w);
+		let mut change: f64 = 0.0;
+		for i in 0..h {
+			for j in 0..w {
+				let (sum, diagonal) = self.neighbours_sum(u, (i, j));
+				let mut new = (f[i*w + j] + sum) / diagonal;
+				if projected {
+					new = new.max(0.0);
+				}
+				change = change.max((new - u[i*w + j]).abs());
+				u[i*w + j] = new;
+			}
+		}
+		change
+	}
+
+	// The sum of u over the neighbours of (i, j), zero beyond the grid, and the number of the neighbours.
+	fn neighbours_sum(&self, u: &[f64], (i, j): (usize, usize)) -> (f64, f64) {
+		let (h, w) = (self.h, self.w);
+		let at = |di: isize, dj: isize| {
+			let (ti, tj) = (i as isize + di, j as isize + dj);
+			if ti >= 0 && tj >= 0 && (ti as usize) < h && (tj as usize) < w {u[ti as usize * w + tj as usize]} else {0.0}
+		};
+		let sum = at(-1, 0) + at(1, 0) + at(0, -1) + at(0, 1);
+		if self.moore {
+			(sum + at(-1, -1) + at(-1, 1) + at(1, -1) + at(1, 1), 8.0)
+		} else {
+			(sum, 4.0)
+		}
+	}
+
+	// f - A·u.
+	fn residual(&self, u: &[f64], f: &[f64]) -> Vec<f64> {
+		let w = self.w;
+		(0..self.h * w).map(|n| {
+			let (sum, diagonal) = self.neighbours_sum(u, (n / w, n % w));
+			f[n] - diagonal * u[n] + sum
+		}).collect()
+	}
+
+	// The sums over 2×2 blocks, in the units of the coarser Laplacian.
+	fn restrict(&self, r: &[f64]) -> Vec<f64> {
+		let (h, w) = (self.h.div_ceil(2), self.w.div_ceil(2));
+		let mut coarse = vec![0.0; h * w];
+		let scale = if self.moore {3.0} else {1.0};
+		for (n, &x) in r.iter().enumerate() {
+			coarse[(n / self.w / 2) * w + n % self.w / 2] += x / scale;
+		}
+		coarse
+	}
+
+	// Bilinear interpolation from the centres of the cells to those of the cells of the 2 times finer grid, with zero
+	// beyond the grid.
+	fn prolong(&self, u: &[f64], (h, w): (usize, usize)) -> Vec<f64> {
+		let at = |i: isize, j: isize| if i >= 0 && j >= 0 && (i as usize) < self.h && (j as usize) < self.w {u[i as usize * self.w + j as usize]} else {0.0};
+		(0..h * w).map(|n| {
+			let (y, x) = (((n / w) as f64 - 0.5) / 2.0, ((n % w) as f64 - 0.5) / 2.0);
+			let (i0, j0) = (y.floor(), x.floor());
+			let (ty, tx) = (y - i0, x - j0);
+			let (i0, j0) = (i0 as isize, j0 as isize);
+			(1.0 - ty) * ((1.0 - tx) * at(i0, j0) + tx * at(i0, j0 + 1)) + ty * ((1.0 - tx) * at(i0 + 1, j0) + tx * at(i0 + 1, j0 + 1))
+		}).collect()
+	}
+}
+
+// Improves u on the first of the levels, projected to u >= 0 if asked, and returns the largest change of a cell.
+// Cells at the obstacle, where u = 0 would like to be lower, don't pass their residual to the correction, which
+// solves the linear problem on the coarser levels.
+fn v_cycle(levels: &[Level], u: &mut [f64], f: &[f64], projected: bool) -> f64 {
+	let level = &levels[0];
+	let before = u.to_vec();
+	if levels.len() == 1 {
+		for _ in 0..COARSEST * COARSEST {
+			level.smooth(u, f, projected);
+		}
+	} else {
+		level.smooth(u, f, projected);
+		level.smooth(u, f, projected);
+		let mut r = level.residual(u, f);
+		if projected {
+			for (r, &u) in r.iter_mut().zip(u.iter()) {
+				if u == 0.0 {
+					*r = 0.0;
+				}
+			}
+		}
+		let r = level.restrict(&r);
+		let coarse = &levels[1];
+		let mut e = vec![0.0; coarse.h * coarse.w];
+		v_cycle(&levels[1..], &mut e, &r, false);
+		for (u, e) in u.iter_mut().zip(coarse.prolong(&e, (level.h, level.w))) {
+			if !projected {
+				*u += e;
+			} else if *u > 0.0 {
+				*u = (*u + e).max(0.0);
+			}
+		}
+		level.smooth(u, f, projected);
+		level.smooth(u, f, projected);
+	}
+	u.iter().zip(&before).fold(0.0, |change: f64, (a, b)| change.max((a - b).abs()))
+}
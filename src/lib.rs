@@ -4,9 +4,30 @@ use std::{
 	fmt,
 	error::Error,
 	convert::TryFrom,
+	hash::{Hash, Hasher},
+	ops,
+	rc::Rc,
 };
 
 mod optimized;
+mod graph;
+mod lattice;
+mod mask;
+mod burning;
+mod random;
+mod smith;
+mod trees;
+mod least_action;
+mod tropical;
+
+pub use graph::{Graph, GraphSandpile, AllRecurrent};
+pub use lattice::{LatticeType, LatticeSandpile};
+pub use mask::{Mask, MASKED, mask_from_string, mask_from_png};
+pub use burning::{Burning, SpanningTree, Tree, UNBURNT};
+pub use tropical::{TropicalCurve, TropicalEdge, Point, Monomial};
+pub use num_bigint::{BigInt, BigUint};
+
+use random::SplitMix64;
 
 pub type Cell = u128;
 pub type Grid = Vec<Vec<Cell>>;
@@ -32,35 +53,183 @@ impl GridType {
 pub enum FiniteGridType {
 	Rectangular,	// Finite rectangular grid with sink all around the grid.
 	Toroidal,   	// Toroidal rectangular grid with sink at the top-left node.
+	Cylindrical,	// Periodic in x, with sink above the top row and below the bottom row.
+	Mobius,     	// Like the cylinder, but the left and right sides are glued with a flip: (i, -1) is (h-1-i, w-1).
+	Klein,      	// Klein bottle: the left and right sides are glued with a flip, the top and bottom ones without it.
+	            	// Sink at the top-left node.
+	Box([Boundary; 4]),	// Rules for the top, right, bottom and left sides. If none of them is a sink, the sink is at the top-left node.
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Boundary {
+	Sink,      	// Chips leaving through the side are lost.
+	Periodic,  	// Chips leaving through the side enter from the opposite side.
+	           	// If the opposite side is not periodic, the grid is a directed graph.
+	Reflecting,	// Chips leaving through the side return to the cell they were sent from.
+}
+
+impl FiniteGridType {
+	// Rules for the top, right, bottom and left sides, or None for the surfaces glued with a flip.
+	pub const fn sides(&self) -> Option<[Boundary; 4]> {
+		match *self {
+			FiniteGridType::Rectangular => Some([Boundary::Sink; 4]),
+			FiniteGridType::Toroidal => Some([Boundary::Periodic; 4]),
+			FiniteGridType::Cylindrical => Some([Boundary::Sink, Boundary::Periodic, Boundary::Sink, Boundary::Periodic]),
+			FiniteGridType::Box(sides) => Some(sides),
+			FiniteGridType::Mobius | FiniteGridType::Klein => None,
+		}
+	}
+
+	// Whether the sink is the top-left node rather than a node outside the grid.
+	pub fn sink_in_grid(&self) -> bool {
+		match self.sides() {
+			Some(sides) => !sides.contains(&Boundary::Sink),
+			None => *self == FiniteGridType::Klein,
+		}
+	}
+
+	// Whether a periodic side is opposite to a non-periodic one.
+	pub fn is_directed(&self) -> bool {
+		match self.sides() {
+			Some([top, right, bottom, left]) =>
+				(top == Boundary::Periodic) != (bottom == Boundary::Periodic) || (left == Boundary::Periodic) != (right == Boundary::Periodic),
+			None => false,
+		}
+	}
+
+	// The cell at offset (di, dj) from (i, j) in an h×w grid, or None if the offset leads beyond the boundary to the sink.
+	// A reflecting side gives the cell (i, j) itself.
+	fn neighbour(&self, (h, w): (usize, usize), (i, j): (usize, usize), (di, dj): (isize, isize)) -> Option<(usize, usize)> {
+		let (h, w) = (h as isize, w as isize);
+		let (mut ti, tj) = (i as isize + di, j as isize + dj);
+		if ti >= 0 && ti < h && tj >= 0 && tj < w {
+			return Some((ti as usize, tj as usize));
+		}
+		let Some([top, right, bottom, left]) = self.sides() else {
+			if tj.div_euclid(w) % 2 != 0 {
+				ti = h-1 - ti;
+			}
+			if *self == FiniteGridType::Klein {
+				ti = ti.rem_euclid(h);
+			}
+			if ti < 0 || ti >= h {
+				return None;
+			}
+			return Some((ti as usize, tj.rem_euclid(w) as usize));
+		};
+		let vertical = if ti < 0 {Some(top)} else if ti >= h {Some(bottom)} else {None};
+		let horizontal = if tj < 0 {Some(left)} else if tj >= w {Some(right)} else {None};
+		if vertical == Some(Boundary::Sink) || horizontal == Some(Boundary::Sink) {
+			None
+		} else if vertical == Some(Boundary::Reflecting) || horizontal == Some(Boundary::Reflecting) {
+			Some((i, j))
+		} else {
+			Some((ti.rem_euclid(h) as usize, tj.rem_euclid(w) as usize))
+		}
+	}
+
+	// Cells of an h×w grid that receive chips when (i, j) topples, with the numbers of chips; chips lost to the sink are omitted.
+	fn topple_to(&self, neighbourhood: &Neighbourhood, (h, w): (usize, usize), (i, j): (usize, usize), topple_to: &mut Vec<(usize, usize, Cell)>) {
+		let sink_in_grid = self.sink_in_grid();
+		for &(di, dj, wt) in neighbourhood.offsets((i, j)) {
+			match self.neighbour((h, w), (i, j), (di, dj)) {
+				Some((0, 0)) if sink_in_grid => {},
+				Some((ti, tj)) => topple_to.push((ti, tj, wt)),
+				None => {},
+			}
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Neighbourhood {
 	VonNeumann,
 	Moore,
+	Triangular,	// Triangular lattice: odd rows are shifted half a cell to the right.
+	Hexagonal, 	// Honeycomb lattice as a brick wall: (i, j) is linked to the row above if i+j is even and to the row below otherwise.
+	           	// On the torus, both lattices need an even number of rows.
+	Custom(Rc<[(isize, isize, Cell)]>),	// Offsets (di, dj) to the neighbours with numbers of chips they get, see `Neighbourhood::custom`.
 }
 
 impl Neighbourhood {
-	pub const fn neighbours(&self) -> Cell {
-		match *self {
+	// Toppling threshold, i.e. the number of chips given away by a toppling.
+	pub fn neighbours(&self) -> Cell {
+		match self {
 			Neighbourhood::VonNeumann => 4,
 			Neighbourhood::Moore => 8,
+			Neighbourhood::Triangular => 6,
+			Neighbourhood::Hexagonal => 3,
+			Neighbourhood::Custom(stencil) => stencil.iter().map(|&(_, _, w)| w).sum(),
+		}
+	}
+
+	// Stencil of (di, dj, weight) offsets. Repeated offsets are merged, offsets with zero weight are dropped.
+	pub fn custom(mut stencil: Vec<(isize, isize, Cell)>) -> Result<Neighbourhood, SandpileError> {
+		stencil.retain(|&(_, _, w)| w > 0);
+		stencil.sort_unstable();
+		let mut merged: Vec<(isize, isize, Cell)> = Vec::with_capacity(stencil.len());
+		for (di, dj, w) in stencil {
+			if (di, dj) == (0, 0) {
+				return Err(SandpileError::SelfNeighbour);
+			}
+			match merged.last_mut() {
+				Some((li, lj, lw)) if (*li, *lj) == (di, dj) => *lw += w,
+				_ => merged.push((di, dj, w)),
+			}
+		}
+		if merged.is_empty() {
+			return Err(SandpileError::EmptyStencil);
+		}
+		Ok(Neighbourhood::Custom(merged.into()))
+	}
+
+	// Offsets (di, dj, weight) of the neighbours of the cell (i, j), where only the parities of i and j matter.
+	fn offsets(&self, (i, j): (usize, usize)) -> &[(isize, isize, Cell)] {
+		match self {
+			Neighbourhood::VonNeumann => &[(-1, 0, 1), (0, -1, 1), (1, 0, 1), (0, 1, 1)],
+			Neighbourhood::Moore => &[(-1, 0, 1), (0, -1, 1), (1, 0, 1), (0, 1, 1), (-1, -1, 1), (-1, 1, 1), (1, -1, 1), (1, 1, 1)],
+			Neighbourhood::Triangular if i % 2 == 0 => &[(0, -1, 1), (0, 1, 1), (-1, -1, 1), (-1, 0, 1), (1, -1, 1), (1, 0, 1)],
+			Neighbourhood::Triangular => &[(0, -1, 1), (0, 1, 1), (-1, 0, 1), (-1, 1, 1), (1, 0, 1), (1, 1, 1)],
+			Neighbourhood::Hexagonal if (i + j) % 2 == 0 => &[(0, -1, 1), (0, 1, 1), (-1, 0, 1)],
+			Neighbourhood::Hexagonal => &[(0, -1, 1), (0, 1, 1), (1, 0, 1)],
+			Neighbourhood::Custom(stencil) => stencil,
+		}
+	}
+
+	// How far a toppling can throw chips along each axis.
+	fn radius(&self) -> usize {
+		match self {
+			Neighbourhood::Custom(stencil) => stencil.iter().map(|&(di, dj, _)| di.unsigned_abs().max(dj.unsigned_abs())).max().unwrap_or(0),
+			_ => 1,
+		}
+	}
+
+	// Whether every cell gives to each neighbour as many chips as it gets from it, barring boundary effects.
+	pub fn is_symmetric(&self) -> bool {
+		match self {
+			Neighbourhood::Custom(stencil) => stencil.iter().all(|&(di, dj, w)| stencil.contains(&(-di, -dj, w))),
+			_ => true,
 		}
 	}
 }
 
-#[derive(Debug, Clone, Hash)]
+#[derive(Debug, Clone)]
 pub struct GridSandpile {
 	grid_type: GridType,
 	neighbourhood: Neighbourhood,
+	mask: Option<Rc<Mask>>,	// Cells of a finite grid which belong to the domain. None means all of them.
+	background: Option<Rc<Grid>>,	// Tile of heights repeated all over the infinite grid beyond the stored cells, with its top-left cell at the origin.
+	                             	// None means no chips.
 	grid: Grid,
 	last_topple: u64,
+	odometer: Option<Grid>,	// Topplings of every stored cell during the last stabilization. None if the sandpile hasn't been toppled.
 }
 
 #[derive(Debug, Hash)]
 pub struct FiniteGridSandpile<'a> {
 	grid_type: FiniteGridType,
 	neighbourhood: Neighbourhood,
+	mask: Option<Rc<Mask>>,
 	grid: &'a Grid,
 	last_topple: u64,
 }
@@ -73,18 +242,40 @@ impl<'a> std::convert::AsRef<Grid> for FiniteGridSandpile<'a> {
 
 impl PartialEq for GridSandpile {
 	fn eq(&self, other: &GridSandpile) -> bool {
-		self.grid_type == other.grid_type && self.neighbourhood == other.neighbourhood && self.grid == other.grid
+		self.grid_type == other.grid_type && self.neighbourhood == other.neighbourhood && self.mask == other.mask && self.background == other.background && self.grid == other.grid
 	}
 }
 
 impl Eq for GridSandpile {}
 
+impl Hash for GridSandpile {
+	fn hash<H: Hasher>(&self, state: &mut H) {
+		self.grid_type.hash(state);
+		self.neighbourhood.hash(state);
+		self.mask.hash(state);
+		self.background.hash(state);
+		self.grid.hash(state);
+	}
+}
+
+// Height of the background tile at the stored cell (i, j) of an infinite grid with the origin at (oy, ox).
+fn background_at(background: Option<&Grid>, (i, j): (usize, usize), (oy, ox): (usize, usize)) -> Cell {
+	match background {
+		Some(tile) => tile[(i as isize - oy as isize).rem_euclid(tile.len() as isize) as usize][(j as isize - ox as isize).rem_euclid(tile[0].len() as isize) as usize],
+		None => 0,
+	}
+}
+
 pub const VIS: [char; 9] = [' ', '.', ':', '&', '#', '5', '6', '7', '8'];
 
 impl fmt::Display for GridSandpile {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-		for row in &self.grid {
-			for el in row {
+		for (i, row) in self.grid.iter().enumerate() {
+			for (j, el) in row.iter().enumerate() {
+				if let Some(mask) = &self.mask && !mask[i][j] {
+					write!(f, "{MASKED}")?;
+					continue;
+				}
 				write!(f, "{}", VIS[if *el < 8 {*el} else {8} as usize])?;
 			}
 			writeln!(f)?;
@@ -93,6 +284,72 @@ impl fmt::Display for GridSandpile {
 	}
 }
 
+// The operators panic where the checked variants return errors.
+impl ops::Add for &GridSandpile {
+	type Output = GridSandpile;
+
+	fn add(self, p: &GridSandpile) -> GridSandpile {
+		self.checked_add(p).unwrap()
+	}
+}
+
+impl ops::Add for GridSandpile {
+	type Output = GridSandpile;
+
+	fn add(mut self, p: GridSandpile) -> GridSandpile {
+		GridSandpile::add(&mut self, &p).unwrap();
+		self
+	}
+}
+
+impl ops::Sub for &GridSandpile {
+	type Output = GridSandpile;
+
+	fn sub(self, p: &GridSandpile) -> GridSandpile {
+		self.checked_sub(p).unwrap()
+	}
+}
+
+impl ops::Sub for GridSandpile {
+	type Output = GridSandpile;
+
+	fn sub(self, p: GridSandpile) -> GridSandpile {
+		self.checked_sub(&p).unwrap()
+	}
+}
+
+impl ops::Neg for &GridSandpile {
+	type Output = GridSandpile;
+
+	fn neg(self) -> GridSandpile {
+		self.checked_neg().unwrap()
+	}
+}
+
+impl ops::Neg for GridSandpile {
+	type Output = GridSandpile;
+
+	fn neg(self) -> GridSandpile {
+		self.checked_neg().unwrap()
+	}
+}
+
+impl ops::Mul<u128> for &GridSandpile {
+	type Output = GridSandpile;
+
+	fn mul(self, k: u128) -> GridSandpile {
+		self.multiple(k)
+	}
+}
+
+impl ops::Mul<u128> for GridSandpile {
+	type Output = GridSandpile;
+
+	fn mul(self, k: u128) -> GridSandpile {
+		self.multiple(k)
+	}
+}
+
 impl GridSandpile {
 	fn verify_rectangular_grid(grid: Grid) -> Result<Grid, SandpileError> {
 		if grid.is_empty() {
@@ -112,14 +369,31 @@ impl GridSandpile {
 	}
 
 	pub fn from_grid(grid_type: GridType, neighbourhood: Neighbourhood, grid: Grid) -> Result<GridSandpile, SandpileError> {
+		Self::from_grid_with_mask(grid_type, neighbourhood, None, grid)
+	}
+
+	// Cells outside the mask act as sink, and chips in them are lost.
+	pub fn from_grid_masked(grid_type: GridType, neighbourhood: Neighbourhood, mask: Rc<Mask>, grid: Grid) -> Result<GridSandpile, SandpileError> {
+		Self::from_grid_with_mask(grid_type, neighbourhood, Some(mask), grid)
+	}
+
+	fn from_grid_with_mask(grid_type: GridType, neighbourhood: Neighbourhood, mask: Option<Rc<Mask>>, grid: Grid) -> Result<GridSandpile, SandpileError> {
 		let grid = Self::verify_rectangular_grid(grid)?;
+		if let Some(mask) = &mask {
+			grid_type.finite()?;
+			mask::verify_mask(mask, (grid[0].len(), grid.len()))?;
+		}
+		let optimized = matches!(neighbourhood, Neighbourhood::VonNeumann | Neighbourhood::Moore);
 		let mut sandpile = GridSandpile {
 			grid_type,
 			neighbourhood,
+			mask,
+			background: None,
 			grid,
 			last_topple: 0,
+			odometer: None,
 		};
-		if grid_type == GridType::Infinite(0, 0) && sandpile.grid.len() == 1 && sandpile.grid[0].len() == 1 {
+		if grid_type == GridType::Infinite(0, 0) && sandpile.grid.len() == 1 && sandpile.grid[0].len() == 1 && optimized {
 			sandpile.delta00_infinite_optimized();
 			return Ok(sandpile)
 		}
@@ -127,11 +401,67 @@ impl GridSandpile {
 		Ok(sandpile)
 	}
 
+	// Chips of the grid are put above the background, which has to be stable.
+	// It also has to be non-explosive, or the toppling never ends, as with one chip on 3 everywhere for von Neumann.
+	#[allow(clippy::needless_range_loop)]
+	pub fn from_grid_on_background(grid_type: GridType, neighbourhood: Neighbourhood, background: Rc<Grid>, grid: Grid) -> Result<GridSandpile, SandpileError> {
+		let GridType::Infinite(oy, ox) = grid_type else {
+			return Err(SandpileError::FiniteBackground);
+		};
+		let mut grid = Self::verify_rectangular_grid(grid)?;
+		let background = Rc::new(Self::verify_rectangular_grid(Rc::unwrap_or_clone(background))?);
+		if background.iter().flatten().any(|&el| el >= neighbourhood.neighbours()) {
+			return Err(SandpileError::UnstableBackground);
+		}
+		for i in 0..grid.len() {
+			for j in 0..grid[i].len() {
+				grid[i][j] += background_at(Some(&background), (i, j), (oy, ox));
+			}
+		}
+		let mut sandpile = GridSandpile {
+			grid_type,
+			neighbourhood,
+			mask: None,
+			background: Some(background),
+			grid,
+			last_topple: 0,
+			odometer: None,
+		};
+		sandpile.topple();
+		Ok(sandpile)
+	}
+
 	pub fn from_string(grid_type: GridType, neighbourhood: Neighbourhood, (x, y): (usize, usize), s: String) -> Result<GridSandpile, SandpileError> {
+		Self::from_string_with_mask(grid_type, neighbourhood, None, (x, y), s)
+	}
+
+	// Absent cells may be marked with `MASKED`.
+	pub fn from_string_masked(grid_type: GridType, neighbourhood: Neighbourhood, mask: Rc<Mask>, s: String) -> Result<GridSandpile, SandpileError> {
+		let (x, y) = (mask.first().map_or(0, |row| row.len()), mask.len());
+		Self::from_string_with_mask(grid_type, neighbourhood, Some(mask), (x, y), s)
+	}
+
+	fn from_string_with_mask(grid_type: GridType, neighbourhood: Neighbourhood, mask: Option<Rc<Mask>>, (x, y): (usize, usize), s: String) -> Result<GridSandpile, SandpileError> {
+		let g = Self::grid_from_string((x, y), mask.is_some(), s)?;
+		GridSandpile::from_grid_with_mask(grid_type, neighbourhood, mask, g)
+	}
+
+	// The chips are put above the background.
+	pub fn from_string_on_background(grid_type: GridType, neighbourhood: Neighbourhood, background: Rc<Grid>, (x, y): (usize, usize), s: String) -> Result<GridSandpile, SandpileError> {
+		let g = Self::grid_from_string((x, y), false, s)?;
+		GridSandpile::from_grid_on_background(grid_type, neighbourhood, background, g)
+	}
+
+	// Parses the `ascii` output without toppling; absent cells may be marked with `MASKED` if `masked`.
+	pub fn grid_from_string((x, y): (usize, usize), masked: bool, s: String) -> Result<Grid, SandpileError> {
 		let mut g = Vec::new();
 		for line in s.lines() {
 			let mut row = Vec::new();
 			'l: for ch in line.chars() {
+				if ch == MASKED && masked {
+					row.push(0);
+					continue
+				}
 				for (n, &vch) in VIS.iter().enumerate() {
 					if ch == vch {
 						row.push(n as Cell);
@@ -142,61 +472,50 @@ impl GridSandpile {
 			}
 			g.push(row);
 		}
-		if y == 0 || x == 0 || g.len() == 0 {
+		if y == 0 || x == 0 || g.is_empty() {
 			return Err(SandpileError::EmptyGrid);
 		}
 		if g.len() != y || g[0].len() != x {
 			return Err(SandpileError::UnequalDimensions(x, y, g[0].len(), g.len()))
 			// actual error might be UnequalRowLengths, but it doesn't matter
 		}
-		GridSandpile::from_grid(grid_type, neighbourhood, g)
+		Ok(g)
 	}
 
 	pub fn add(&mut self, p: &GridSandpile) -> Result<(), SandpileError> {
-		if let (GridType::Infinite(mut o1y, mut o1x), GridType::Infinite(o2y, o2x))
-		 = (self.grid_type, p.grid_type) {
-			if o2x > o1x {
-				for row in self.grid.iter_mut() {
-					let mut prow = vec![0; o2x-o1x];
-					prow.append(row);
-					*row = prow;
-				}
-				o1x = o2x;
-			}
-			if o2y > o1y {
-				let mut pgrid = vec![vec![0; self.grid.len()]; o2y-o1y];
-				pgrid.append(&mut self.grid);
-				self.grid = pgrid;
-				o1y = o2y;
-			}
-			self.grid_type = GridType::Infinite(o1y, o1x);
-			for i in (o1y-o2y)..self.grid.len() {
-				if i+o2y-o1y >= p.grid.len() {
-					break
-				}
-				for j in (o1x-o2x)..self.grid[0].len() {
-					if j+o2x-o1x >= p.grid[0].len() {
-						break
-					}
-					self.grid[i][j] += p.grid[i+o2y-o1y][j+o2x-o1x];
-				}
-				if self.grid[i].len() < p.grid[0].len()+o2x-o1x {
-					for el in &p.grid[i+o2y-o1y][self.grid[i].len()+o1x-o2x..] {
-						self.grid[i].push(*el);
-					}
-				}
+		if let (GridType::Infinite(o1y, o1x), GridType::Infinite(o2y, o2x)) = (self.grid_type, p.grid_type) {
+			if p.background != self.background {
+				return Err(SandpileError::UnequalBackgrounds);
+			}
+			// The union of the stored rectangles, with the chips of p above the background added to self.
+			let (oy, ox) = (o1y.max(o2y), o1x.max(o2x));
+			let h = (oy - o1y + self.grid.len()).max(oy - o2y + p.grid.len());
+			let w = (ox - o1x + self.grid[0].len()).max(ox - o2x + p.grid[0].len());
+			let background = self.background.as_deref();
+			let mut grid: Grid = (0..h).map(|i| (0..w).map(|j| background_at(background, (i, j), (oy, ox))).collect()).collect();
+			for (i, row) in self.grid.iter().enumerate() {
+				grid[i+oy-o1y][ox-o1x..ox-o1x+row.len()].copy_from_slice(row);
 			}
-			if self.grid.len() < p.grid.len()+o2y-o1y {
-				for row in &p.grid[self.grid.len()+o1y-o2y..] {
-					self.grid.push(row.clone());
+			for (i, row) in p.grid.iter().enumerate() {
+				for (j, &el) in row.iter().enumerate() {
+					let (ti, tj) = (i+oy-o2y, j+ox-o2x);
+					grid[ti][tj] = match (grid[ti][tj] + el).checked_sub(background_at(background, (ti, tj), (oy, ox))) {
+						Some(el) => el,
+						None => return Err(SandpileError::BelowBackground),
+					};
 				}
 			}
+			self.grid = grid;
+			self.grid_type = GridType::Infinite(oy, ox);
 			self.topple();
 			return Ok(())
 		}
 		if p.grid_type != self.grid_type {
 			return Err(SandpileError::UnequalTypes(self.grid_type, p.grid_type));
 		}
+		if p.mask != self.mask {
+			return Err(SandpileError::UnequalMasks);
+		}
 		if p.grid.len() != self.grid.len() || p.grid[0].len() != self.grid[0].len() {
 			return Err(SandpileError::UnequalDimensions(
 			self.grid.len(), self.grid[0].len(), p.grid.len(), p.grid[0].len()));
@@ -205,6 +524,7 @@ impl GridSandpile {
 		Ok(())
 	}
 	
+	#[allow(clippy::needless_range_loop)]
 	fn add_grid_unchecked(&mut self, pgrid: &Grid) {
 		for i in 0..self.grid.len() {
 			for j in 0..self.grid[0].len() {
@@ -213,144 +533,222 @@ impl GridSandpile {
 		}
 		self.topple();
 	}
+
+	// k·s by doubling and adding, in O(log k) stabilizations. 0·s has no chips above the background.
+	// The topplings of all the stabilizations are counted. The odometer is that of stabilizing k copies of s at once,
+	// so the odometers of the summands, which are themselves multiples of s, are added to that of every stabilization.
+	pub fn multiple(&self, k: u128) -> GridSandpile {
+		self.multiple_with_powers(k, |_, _| ())
+	}
+
+	// The same, passing every power 2^n·s up to k to each_power as soon as it is stabilized, with n, starting from s itself.
+	// For one chip on the infinite grid, these are the piles of 1, 2, 4, … chips.
+	pub fn multiple_with_powers(&self, mut k: u128, mut each_power: impl FnMut(u32, &GridSandpile)) -> GridSandpile {
+		let mut result: Option<(GridSandpile, Grid)> = None;
+		let mut power = (self.clone(), vec![vec![0; self.grid[0].len()]; self.grid.len()]);
+		let mut topplings = 0;
+		let mut n = 0;
+		while k > 0 {
+			each_power(n, &power.0);
+			if k & 1 == 1 {
+				result = Some(match result {
+					Some((mut result, odometer)) => {
+						let old_type = result.grid_type;
+						result.add(&power.0).unwrap();
+						topplings += result.last_topple;
+						let odometer = result.odometer_sum(&[(old_type, &odometer), (power.0.grid_type, &power.1), (result.grid_type, result.odometer.as_ref().unwrap())]);
+						(result, odometer)
+					},
+					None => power.clone(),
+				});
+			}
+			k >>= 1;
+			if k > 0 {
+				let (mut p, odometer) = power.clone();
+				p.add(&power.0).unwrap();
+				topplings += p.last_topple;
+				let odometer = p.odometer_sum(&[(power.0.grid_type, &odometer), (power.0.grid_type, &odometer), (p.grid_type, p.odometer.as_ref().unwrap())]);
+				power = (p, odometer);
+				n += 1;
+			}
+		}
+		let (mut result, odometer) = result.unwrap_or_else(|| {
+			let result = GridSandpile {grid: self.background_grid(), ..self.clone()};
+			let odometer = vec![vec![0; result.grid[0].len()]; result.grid.len()];
+			(result, odometer)
+		});
+		result.last_topple = topplings;
+		result.odometer = Some(odometer);
+		result
+	}
+
+	// The sum of the odometers in the stored cells of self, which cover all of them. They are aligned by the origins of their grid types.
+	fn odometer_sum(&self, odometers: &[(GridType, &Grid)]) -> Grid {
+		let origin = |grid_type| match grid_type {
+			GridType::Infinite(oy, ox) => (oy, ox),
+			GridType::Finite(_) => (0, 0),
+		};
+		let (oy, ox) = origin(self.grid_type);
+		let mut sum = vec![vec![0; self.grid[0].len()]; self.grid.len()];
+		for &(grid_type, odometer) in odometers {
+			let (sy, sx) = origin(grid_type);
+			for (i, row) in odometer.iter().enumerate() {
+				for (j, &n) in row.iter().enumerate() {
+					sum[i + oy - sy][j + ox - sx] += n;
+				}
+			}
+		}
+		sum
+	}
+
+	pub fn checked_add(&self, p: &GridSandpile) -> Result<GridSandpile, SandpileError> {
+		let mut sandpile = self.clone();
+		sandpile.add(p)?;
+		Ok(sandpile)
+	}
+
+	// a - b = a + inverse(b), only on finite grids.
+	pub fn checked_sub(&self, p: &GridSandpile) -> Result<GridSandpile, SandpileError> {
+		self.checked_add(&p.checked_neg()?)
+	}
+
+	pub fn checked_neg(&self) -> Result<GridSandpile, SandpileError> {
+		Ok(FiniteGridSandpile::try_from(self)?.inverse())
+	}
 	
 	pub fn into_grid(self) -> Grid {
 		self.grid
 	}
 
 	fn topple(&mut self) -> u64 {
-		if self.grid_type == GridType::Finite(FiniteGridType::Toroidal) {
+		if let GridType::Infinite(..) = self.grid_type && self.background.is_none()
+		 && matches!(self.neighbourhood, Neighbourhood::VonNeumann | Neighbourhood::Moore)
+		 && let Some(count) = self.symmetric_infinite_optimized() {
+			return count
+		}
+		// Heavy rectangles first fire a lower bound of the odometer, and the rest topples as usual.
+		let fired = self.least_action_fire();
+		self.topple_unoptimized();
+		if let Some(fired) = fired {
+			let odometer = self.odometer.get_or_insert_default();
+			for (row, fired_row) in odometer.iter_mut().zip(&fired) {
+				for (n, &k) in row.iter_mut().zip(fired_row) {
+					*n += k;
+				}
+			}
+			self.last_topple += fired.iter().flatten().sum::<Cell>() as u64;
+		}
+		self.last_topple
+	}
+
+	fn topple_unoptimized(&mut self) -> u64 {
+		if let GridType::Finite(t) = self.grid_type && t.sink_in_grid() {
 			self.grid[0][0] = 0;
 		}
+		if let Some(mask) = &self.mask {
+			for i in 0..self.grid.len() {
+				for j in 0..self.grid[i].len() {
+					if !mask[i][j] {
+						self.grid[i][j] = 0;
+					}
+				}
+			}
+		}
+		let threshold = self.neighbourhood.neighbours();
+		let background = self.background.clone();
 		let mut excessive = Vec::new();
 		let mut ex2 = Vec::new();
 		for i in 0..self.grid.len() {
 			for j in 0..self.grid[i].len() {
-				if self.grid[i][j] >= self.neighbourhood.neighbours() {
+				if self.grid[i][j] >= threshold {
 					excessive.push((i, j));
 				}
 			}
 		}
 		let mut count = 0;
+		let mut odometer = vec![vec![0; self.grid[0].len()]; self.grid.len()];
 		let mut topple_to = Vec::new();
+		// The infinite grid grows at least twice at a time, and in the end keeps the former cells and the neighbourhoods
+		// of the toppled ones: rows and columns from and to.
+		let mut kept = (0, 0, self.grid.len() - 1, self.grid[0].len() - 1);
 		while !excessive.is_empty() {
-			let (mut inc_i, mut inc_j) = (false, false);
+			let (mut inc_i, mut inc_j) = (0, 0);
 			for &(i, j) in &excessive {
-				let i = if inc_i { i+1 } else {i};
-				let j = if inc_j { j+1 } else {j};
-				let d = self.grid[i][j] / self.neighbourhood.neighbours();
+				let (i, j) = (i + inc_i, j + inc_j);
+				let d = self.grid[i][j] / threshold;
 				if d == 0 {
 					continue;
 				}
-				self.grid[i][j] %= self.neighbourhood.neighbours();
+				self.grid[i][j] %= threshold;
 				count += d as u64;
+				odometer[i][j] += d;
 				topple_to.clear();
 				match self.grid_type {
-					GridType::Finite(FiniteGridType::Rectangular) => {
-						if i > 0 {
-							topple_to.push((i-1, j));
-						}
-						if j > 0 {
-							topple_to.push((i, j-1));
-						}
-						if i < self.grid.len()-1 {
-							topple_to.push((i+1, j));
-						}
-						if j < self.grid[i].len()-1 {
-							topple_to.push((i, j+1));
-						}
-						if self.neighbourhood == Neighbourhood::Moore {
-							if i > 0 && j > 0 {
-								topple_to.push((i-1, j-1));
-							}
-							if i > 0 && j < self.grid[i].len()-1 {
-								topple_to.push((i-1, j+1));
-							}
-							if i < self.grid.len()-1 && j > 0 {
-								topple_to.push((i+1, j-1));
-							}
-							if i < self.grid.len()-1 && j < self.grid[i].len()-1 {
-								topple_to.push((i+1, j+1));
-							}
-						}
-					},
-					GridType::Finite(FiniteGridType::Toroidal) => {
-						let im1 = if i > 0 {i-1} else {self.grid.len()-1};
-						if !(im1 == 0 && j == 0) {
-							topple_to.push((im1, j));
-						}
-						let jm1 = if j > 0 {j-1} else {self.grid[0].len()-1};
-						if !(i == 0 && jm1 == 0) {
-							topple_to.push((i, jm1));
-						}
-						let ip1 = if i < self.grid.len()-1 {i+1} else {0};
-						if !(ip1 == 0 && j == 0) {
-							topple_to.push((ip1, j));
-						}
-						let jp1 = if j < self.grid[i].len()-1 {j+1} else {0};
-						if !(i == 0 && jp1 == 0) {
-							topple_to.push((i, jp1));
-						}
-						if self.neighbourhood == Neighbourhood::Moore {
-							if !(im1 == 0 && jm1 == 0) {
-								topple_to.push((im1, jm1));
-							}
-							if !(im1 == 0 && jp1 == 0) {
-								topple_to.push((im1, jp1));
-							}
-							if !(ip1 == 0 && jm1 == 0) {
-								topple_to.push((ip1, jm1));
-							}
-							if !(ip1 == 0 && jp1 == 0) {
-								topple_to.push((ip1, jp1));
-							}
+					GridType::Finite(t) => {
+						t.topple_to(&self.neighbourhood, (self.grid.len(), self.grid[0].len()), (i, j), &mut topple_to);
+						if let Some(mask) = &self.mask {
+							topple_to.retain(|&(ti, tj, _)| mask[ti][tj]);
 						}
 					},
-					GridType::Infinite(oy, ox) => {
+					GridType::Infinite(mut oy, mut ox) => {
 						let (mut i, mut j) = (i, j);
-						if j == 0 {
-							for row in self.grid.iter_mut() {
-								row.insert(0, 0);
+						let r = self.neighbourhood.radius();
+						if j < r {
+							let k = (r - j).max(self.grid[0].len());
+							for (ti, row) in self.grid.iter_mut().enumerate() {
+								row.splice(0..0, (0..k).map(|tj| background_at(background.as_deref(), (ti, tj), (oy, ox + k))));
+							}
+							for row in odometer.iter_mut() {
+								row.splice(0..0, std::iter::repeat_n(0, k));
 							}
 							for (_, tj) in ex2.iter_mut() {
-								*tj += 1;
+								*tj += k;
 							}
-							j = 1;
-							inc_j = true;
-							self.grid_type = GridType::Infinite(oy, ox+1);
+							j += k;
+							inc_j += k;
+							ox += k;
+							(kept.1, kept.3) = (kept.1 + k, kept.3 + k);
 						}
-						if j + 1 == self.grid[0].len() {
-							for row in self.grid.iter_mut() {
-								row.push(0);
+						if j + r >= self.grid[0].len() {
+							let l = (j + r + 1).max(2 * self.grid[0].len());
+							for (ti, row) in self.grid.iter_mut().enumerate() {
+								let w = row.len();
+								row.extend((w..l).map(|tj| background_at(background.as_deref(), (ti, tj), (oy, ox))));
+							}
+							for row in odometer.iter_mut() {
+								row.resize(l, 0);
 							}
 						}
-						if i == 0 {
-							self.grid.insert(0, vec![0; self.grid[0].len()]);
+						if i < r {
+							let k = (r - i).max(self.grid.len());
+							let l = self.grid[0].len();
+							self.grid.splice(0..0, (0..k).map(|ti| (0..l).map(|tj| background_at(background.as_deref(), (ti, tj), (oy + k, ox))).collect()));
+							odometer.splice(0..0, std::iter::repeat_n(vec![0; l], k));
 							for (ti, _) in ex2.iter_mut() {
-								*ti += 1;
+								*ti += k;
 							}
-							i = 1;
-							inc_i = true;
-							self.grid_type = GridType::Infinite(oy+1, ox);
+							i += k;
+							inc_i += k;
+							oy += k;
+							(kept.0, kept.2) = (kept.0 + k, kept.2 + k);
 						}
-						if i + 1 == self.grid.len() {
-							self.grid.push(vec![0; self.grid[0].len()]);
+						if i + r >= self.grid.len() {
+							let (h, l) = (self.grid.len(), self.grid[0].len());
+							let h2 = (i + r + 1).max(2 * h);
+							self.grid.extend((h..h2).map(|ti| (0..l).map(|tj| background_at(background.as_deref(), (ti, tj), (oy, ox))).collect()));
+							odometer.resize(h2, vec![0; l]);
 						}
-						topple_to.push((i-1, j));
-						topple_to.push((i+1, j));
-						topple_to.push((i, j-1));
-						topple_to.push((i, j+1));
-						if self.neighbourhood == Neighbourhood::Moore {
-							topple_to.push((i-1, j-1));
-							topple_to.push((i+1, j-1));
-							topple_to.push((i-1, j+1));
-							topple_to.push((i+1, j+1));
+						kept = (kept.0.min(i - r), kept.1.min(j - r), kept.2.max(i + r), kept.3.max(j + r));
+						self.grid_type = GridType::Infinite(oy, ox);
+						// Parities of the coordinates relative to the origin.
+						for &(di, dj, w) in self.neighbourhood.offsets((i + oy, j + ox)) {
+							topple_to.push(((i as isize + di) as usize, (j as isize + dj) as usize, w));
 						}
 					},
 				};
-				for &(ti, tj) in &topple_to {
-					self.grid[ti][tj] += d;
-					if self.grid[ti][tj] >= self.neighbourhood.neighbours() {
+				for &(ti, tj, w) in &topple_to {
+					self.grid[ti][tj] += d * w;
+					if self.grid[ti][tj] >= threshold {
 						ex2.push((ti, tj));
 					}
 				}
@@ -358,21 +756,75 @@ impl GridSandpile {
 			(excessive, ex2) = (ex2, excessive);
 			ex2.clear();
 		}
+		if let GridType::Infinite(oy, ox) = self.grid_type {
+			let (i0, j0, i1, j1) = kept;
+			for g in [&mut self.grid, &mut odometer] {
+				g.truncate(i1 + 1);
+				g.drain(..i0);
+				for row in g.iter_mut() {
+					row.truncate(j1 + 1);
+					row.drain(..j0);
+				}
+			}
+			self.grid_type = GridType::Infinite(oy - i0, ox - j0);
+		}
 		self.last_topple = count;
+		self.odometer = Some(odometer);
 		count
 	}
 	
+	// On a background, only the chips above it are counted.
 	pub fn chips_count(&self) -> u64 {
-		self.grid.iter().map(|row| -> u64 { row.iter().map(|&x| x as u64).sum() }).sum()
+		let count: u128 = self.grid.iter().map(|row| -> u128 { row.iter().sum() }).sum();
+		(count - self.background_grid().iter().flatten().sum::<Cell>()) as u64
+	}
+
+	// The background heights at the stored cells, all zero if there is no background.
+	pub fn background_grid(&self) -> Grid {
+		let origin = match self.grid_type {
+			GridType::Infinite(oy, ox) => (oy, ox),
+			GridType::Finite(_) => (0, 0),
+		};
+		(0..self.grid.len()).map(|i| (0..self.grid[0].len()).map(|j| background_at(self.background.as_deref(), (i, j), origin)).collect()).collect()
+	}
+
+	pub fn background(&self) -> Option<&Grid> {
+		self.background.as_deref()
 	}
 	
 	pub fn last_topple(&self) -> u64 {
 		self.last_topple
 	}
+
+	// How many times every cell toppled during the last stabilization, in the same cells as the grid.
+	pub fn odometer(&self) -> Option<&Grid> {
+		self.odometer.as_ref()
+	}
+
+	// The tropical curve of the odometer of the last stabilization, no toppling being the zero odometer.
+	pub fn tropical_curve(&self) -> Result<TropicalCurve, SandpileError> {
+		let rectangle = matches!(self.grid_type, GridType::Finite(t) if t.sides() == Some([Boundary::Sink; 4]));
+		if !rectangle || self.mask.is_some() || !matches!(self.neighbourhood, Neighbourhood::VonNeumann | Neighbourhood::Moore) {
+			return Err(SandpileError::UnsupportedGrid);
+		}
+		let zeros;
+		let odometer = match &self.odometer {
+			Some(odometer) => odometer,
+			None => {
+				zeros = vec![vec![0; self.grid[0].len()]; self.grid.len()];
+				&zeros
+			},
+		};
+		Ok(TropicalCurve::from_odometer(odometer))
+	}
 	
 	pub fn grid_type(&self) -> GridType {
 		self.grid_type
 	}
+
+	pub fn mask(&self) -> Option<&Mask> {
+		self.mask.as_deref()
+	}
 }
 
 impl<'a, 'b: 'a> TryFrom<&'b GridSandpile> for FiniteGridSandpile<'a> {
@@ -382,7 +834,8 @@ impl<'a, 'b: 'a> TryFrom<&'b GridSandpile> for FiniteGridSandpile<'a> {
 		if let GridType::Finite(grid_type) = s.grid_type {
 			Ok(FiniteGridSandpile {
 				grid_type,
-				neighbourhood: s.neighbourhood,
+				neighbourhood: s.neighbourhood.clone(),
+				mask: s.mask.clone(),
 				grid: &s.grid,
 				last_topple: s.last_topple,
 			})
@@ -403,9 +856,23 @@ impl<'a> FiniteGridSandpile<'a> {
 		} else if grid_type == FiniteGridType::Rectangular && neighbourhood == Neighbourhood::VonNeumann && x % 2 == 0 && y % 2 == 0 && x >= 4 && y >= 4 {
 			return FiniteGridSandpile::neutral_plus_rect_vn_ee_optimized(x/2, y/2, plus)
 		}
+		Self::neutral_plus_with_mask(grid_type, neighbourhood, None, (x, y), plus)
+	}
+
+	// The size of the grid is that of the mask.
+	pub fn neutral_masked(grid_type: FiniteGridType, neighbourhood: Neighbourhood, mask: Rc<Mask>) -> Result<GridSandpile, SandpileError> {
+		let (x, y) = (mask.first().map_or(0, |row| row.len()), mask.len());
+		if x == 0 {
+			return Err(SandpileError::EmptyGrid);
+		}
+		mask::verify_mask(&mask, (x, y))?;
+		Ok(Self::neutral_plus_with_mask(grid_type, neighbourhood, Some(mask), (x, y), 0))
+	}
+
+	fn neutral_plus_with_mask(grid_type: FiniteGridType, neighbourhood: Neighbourhood, mask: Option<Rc<Mask>>, (x, y): (usize, usize), plus: Cell) -> GridSandpile {
 	// Proposition 6.36 of https://people.reed.edu/~davidp/divisors_and_sandpiles/
 		let t = 2 * (neighbourhood.neighbours() - 1);
-		let mut sandpile = GridSandpile::from_grid(GridType::Finite(grid_type), neighbourhood, vec![vec![t; x]; y]).unwrap();
+		let mut sandpile = GridSandpile::from_grid_with_mask(GridType::Finite(grid_type), neighbourhood, mask, vec![vec![t; x]; y]).unwrap();
 		for row in &mut sandpile.grid {
 			for el in row {
 				*el = t + plus - *el;
@@ -415,45 +882,174 @@ impl<'a> FiniteGridSandpile<'a> {
 		sandpile
 	}
 
+	// For an asymmetric stencil, the sink firing does not test recurrence, so Speer's burning configuration is used instead.
 	pub fn burn(grid_type: FiniteGridType, neighbourhood: Neighbourhood, (x, y): (usize, usize)) -> GridSandpile {
+		Self::burn_with_mask(grid_type, neighbourhood, None, (x, y))
+	}
+
+	pub fn burn_masked(grid_type: FiniteGridType, neighbourhood: Neighbourhood, mask: Rc<Mask>) -> Result<GridSandpile, SandpileError> {
+		let (x, y) = (mask.first().map_or(0, |row| row.len()), mask.len());
+		if x == 0 {
+			return Err(SandpileError::EmptyGrid);
+		}
+		mask::verify_mask(&mask, (x, y))?;
+		Ok(Self::burn_with_mask(grid_type, neighbourhood, Some(mask), (x, y)))
+	}
+
+	#[allow(clippy::needless_range_loop)]
+	fn burn_with_mask(grid_type: FiniteGridType, neighbourhood: Neighbourhood, mask: Option<Rc<Mask>>, (x, y): (usize, usize)) -> GridSandpile {
+		if !Self::is_symmetric(grid_type, &neighbourhood, (x, y)) {
+			let graph = Rc::new(Self::graph_with_mask(grid_type, neighbourhood.clone(), mask.as_deref(), (x, y)));
+			let config = GraphSandpile::burn(graph).into_config();
+			let g = config[..x*y].chunks(x).map(|row| row.to_vec()).collect();
+			return GridSandpile::from_grid_with_mask(GridType::Finite(grid_type), neighbourhood, mask, g).unwrap()
+		}
+		// Every cell gets as many chips as it sends to the sink.
 		let mut g = vec![vec![0; x]; y];
-		match grid_type {
-			FiniteGridType::Rectangular => {
-				let border_neighbours = match neighbourhood {
-					Neighbourhood::VonNeumann => 1,
-					Neighbourhood::Moore => 3,
-				};
-				for j in 0..x {
-					g[0][j] = border_neighbours;
-					g[y-1][j] += border_neighbours;
-				}
-				for i in 0..y {
-					g[i][0] += border_neighbours;
-					g[i][x-1] += border_neighbours;
+		let mut topple_to = Vec::new();
+		for i in 0..y {
+			for j in 0..x {
+				topple_to.clear();
+				grid_type.topple_to(&neighbourhood, (y, x), (i, j), &mut topple_to);
+				if let Some(mask) = &mask {
+					topple_to.retain(|&(ti, tj, _)| mask[ti][tj]);
 				}
-				if neighbourhood == Neighbourhood::Moore {
-					for &(i, j) in &[(0, 0), (0, x-1), (y-1, 0), (y-1, x-1)] {
-						g[i][j] -= 1;
+				g[i][j] = neighbourhood.neighbours() - topple_to.iter().map(|&(_, _, w)| w).sum::<Cell>();
+			}
+		}
+		GridSandpile::from_grid_with_mask(GridType::Finite(grid_type), neighbourhood, mask, g).unwrap()
+	}
+
+	// Whether every cell gives to each other cell as many chips as it gets from it.
+	// Fails for asymmetric stencils and for lattices which do not fit the surface, like the triangular lattice on a torus of odd height.
+	pub fn is_symmetric(grid_type: FiniteGridType, neighbourhood: &Neighbourhood, (x, y): (usize, usize)) -> bool {
+		let given = |from: (usize, usize), to: (usize, usize)| -> Cell {
+			neighbourhood.offsets(from).iter()
+				.filter(|&&(di, dj, _)| grid_type.neighbour((y, x), from, (di, dj)) == Some(to))
+				.map(|&(_, _, w)| w).sum()
+		};
+		for i in 0..y {
+			for j in 0..x {
+				for &(di, dj, _) in neighbourhood.offsets((i, j)) {
+					if let Some(t) = grid_type.neighbour((y, x), (i, j), (di, dj)) && given((i, j), t) != given(t, (i, j)) {
+						return false
 					}
 				}
-			},
-			FiniteGridType::Toroidal => {
-				for &(i, j) in &[(0, 1%x), (1%y, 0), (y-1, 0), (0, x-1)] {
-					g[i][j] += 1;
+			}
+		}
+		true
+	}
+
+	// Cell (i, j) becomes vertex i*x + j. The sink is vertex 0 if it is in the grid and the extra vertex x*y otherwise.
+	// The graph is built as directed, since asymmetric stencils and some lattices on a torus of odd height give digraphs.
+	pub fn graph(grid_type: FiniteGridType, neighbourhood: Neighbourhood, (x, y): (usize, usize)) -> Graph {
+		Self::graph_with_mask(grid_type, neighbourhood, None, (x, y))
+	}
+
+	// Cells outside the mask send one chip to the sink and get nothing.
+	fn graph_with_mask(grid_type: FiniteGridType, neighbourhood: Neighbourhood, mask: Option<&Mask>, (x, y): (usize, usize)) -> Graph {
+		let sink = if grid_type.sink_in_grid() {0} else {x*y};
+		let mut adjacency = vec![Vec::new(); if sink == x*y {x*y+1} else {x*y}];
+		let mut topple_to = Vec::new();
+		for i in 0..y {
+			for j in 0..x {
+				let v = i*x + j;
+				if v == sink {
+					continue;
 				}
-				if neighbourhood == Neighbourhood::Moore {
-					for &(i, j) in &[(1%y, 1%x), (1%y, x-1), (y-1, 1%x), (y-1, x-1)] {
-						g[i][j] += 1;
-					}
+				if let Some(mask) = mask && !mask[i][j] {
+					adjacency[v].push((sink, 1));
+					continue;
 				}
-			},
+				topple_to.clear();
+				grid_type.topple_to(&neighbourhood, (y, x), (i, j), &mut topple_to);
+				if let Some(mask) = mask {
+					topple_to.retain(|&(ti, tj, _)| mask[ti][tj]);
+				}
+				for &(ti, tj, w) in &topple_to {
+					adjacency[v].push((ti*x + tj, w));
+				}
+				let to_sink = neighbourhood.neighbours() - topple_to.iter().map(|&(_, _, w)| w).sum::<Cell>();
+				adjacency[v].push((sink, to_sink));
+				adjacency[sink].push((v, to_sink));
+			}
+		}
+		Graph::directed(adjacency, sink).unwrap()
+	}
+
+	// Invariant factors of the sandpile group, see `Graph::invariant_factors`.
+	pub fn invariant_factors(grid_type: FiniteGridType, neighbourhood: Neighbourhood, (x, y): (usize, usize)) -> Vec<BigUint> {
+		Self::graph(grid_type, neighbourhood, (x, y)).invariant_factors()
+	}
+
+	pub fn invariant_factors_masked(grid_type: FiniteGridType, neighbourhood: Neighbourhood, mask: &Mask) -> Result<Vec<BigUint>, SandpileError> {
+		let (x, y) = (mask.first().map_or(0, |row| row.len()), mask.len());
+		if x == 0 {
+			return Err(SandpileError::EmptyGrid);
+		}
+		mask::verify_mask(mask, (x, y))?;
+		Ok(Self::graph_with_mask(grid_type, neighbourhood, Some(mask), (x, y)).invariant_factors())
+	}
+
+	// Order of the sandpile group, that is, the number of spanning trees of the grid with the sink.
+	// Rectangles and tori with the von Neumann and Moore neighbourhoods use the product formulas, other grids the determinant.
+	pub fn group_order(grid_type: FiniteGridType, neighbourhood: Neighbourhood, (x, y): (usize, usize)) -> BigUint {
+		let moore = match neighbourhood {
+			Neighbourhood::VonNeumann => false,
+			Neighbourhood::Moore => true,
+			_ => return Self::graph(grid_type, neighbourhood, (x, y)).group_order(),
+		};
+		match grid_type.sides() {
+			Some([Boundary::Sink, Boundary::Sink, Boundary::Sink, Boundary::Sink]) => trees::grid_trees(false, moore, (x, y)),
+			Some([Boundary::Periodic, Boundary::Periodic, Boundary::Periodic, Boundary::Periodic]) => trees::grid_trees(true, moore, (x, y)),
+			_ => Self::graph(grid_type, neighbourhood, (x, y)).group_order(),
+		}
+	}
+
+	pub fn group_order_masked(grid_type: FiniteGridType, neighbourhood: Neighbourhood, mask: &Mask) -> Result<BigUint, SandpileError> {
+		let (x, y) = (mask.first().map_or(0, |row| row.len()), mask.len());
+		if x == 0 {
+			return Err(SandpileError::EmptyGrid);
+		}
+		mask::verify_mask(mask, (x, y))?;
+		Ok(Self::graph_with_mask(grid_type, neighbourhood, Some(mask), (x, y)).group_order())
+	}
+
+	// Whether two grids of chips, which may be unstable or negative, give the same element of the sandpile group,
+	// see `Graph::equivalent`. Neither of them topples.
+	pub fn equivalent(grid_type: FiniteGridType, neighbourhood: Neighbourhood, a: &[Vec<BigInt>], b: &[Vec<BigInt>]) -> Result<bool, SandpileError> {
+		let (x, y) = (a.first().map_or(0, |row| row.len()), a.len());
+		Self::equivalent_with_mask(grid_type, neighbourhood, None, (x, y), a, b)
+	}
+
+	// The chips of the cells outside the mask don't matter.
+	pub fn equivalent_masked(grid_type: FiniteGridType, neighbourhood: Neighbourhood, mask: &Mask, a: &[Vec<BigInt>], b: &[Vec<BigInt>]) -> Result<bool, SandpileError> {
+		let (x, y) = (mask.first().map_or(0, |row| row.len()), mask.len());
+		mask::verify_mask(mask, (x, y))?;
+		Self::equivalent_with_mask(grid_type, neighbourhood, Some(mask), (x, y), a, b)
+	}
+
+	fn equivalent_with_mask(grid_type: FiniteGridType, neighbourhood: Neighbourhood, mask: Option<&Mask>, (x, y): (usize, usize), a: &[Vec<BigInt>], b: &[Vec<BigInt>]) -> Result<bool, SandpileError> {
+		if x == 0 {
+			return Err(SandpileError::EmptyGrid);
+		}
+		for g in [a, b] {
+			if g.len() != y || g.iter().any(|row| row.len() != x) {
+				return Err(SandpileError::UnequalDimensions(x, y, g.first().map_or(0, |row| row.len()), g.len()))
+			}
+		}
+		let graph = Self::graph_with_mask(grid_type, neighbourhood, mask, (x, y));
+		let config = |g: &[Vec<BigInt>]| {
+			let mut config: Vec<_> = g.iter().flatten().cloned().collect();
+			config.resize(graph.len(), BigInt::ZERO);
+			config
 		};
-		GridSandpile::from_grid(GridType::Finite(grid_type), neighbourhood, g).unwrap()
+		graph.equivalent(&config(a), &config(b))
 	}
 
 	pub fn inverse(&self) -> GridSandpile {
 		let t = 2 * (self.neighbourhood.neighbours() - 1);
-		let mut sandpile = GridSandpile::from_grid(GridType::Finite(self.grid_type), self.neighbourhood, vec![vec![t; self.grid[0].len()]; self.grid.len()]).unwrap();
+		let mut sandpile = GridSandpile::from_grid_with_mask(GridType::Finite(self.grid_type), self.neighbourhood.clone(), self.mask.clone(), vec![vec![t; self.grid[0].len()]; self.grid.len()]).unwrap();
 		for y in 0..self.grid.len() {
 			for x in 0..self.grid[0].len() {
 				sandpile.grid[y][x] = 2 * (t - sandpile.grid[y][x]) - self.grid[y][x];
@@ -463,12 +1059,172 @@ impl<'a> FiniteGridSandpile<'a> {
 		sandpile
 	}
 
-	pub fn order(&self) -> u64
+	// The order in the sandpile group, found from (L^T)^-1·grid, L being the reduced Laplacian. The sandpile needn't be recurrent.
+	pub fn order(&self) -> BigUint {
+		self.graph_sandpile().order()
+	}
+
+	// Lengths of the tail and of the cycle of the multiples of the sandpile, see `GraphSandpile::rho`.
+	pub fn rho(&self) -> (u64, BigUint) {
+		self.graph_sandpile().rho()
+	}
+
+	// Dhar's burning algorithm, see `GraphSandpile::burning`. Only for grids where every cell gives its neighbours
+	// as many chips as it gets from them.
+	pub fn burning(&self) -> Result<Burning, SandpileError> {
+		let (x, y) = (self.grid[0].len(), self.grid.len());
+		let steps = self.graph_sandpile().burning()?;
+		let steps = (0..y).map(|i| (0..x).map(|j| match &self.mask {
+			Some(mask) if !mask[i][j] => Some(0),
+			_ => steps[i*x + j],
+		}).collect()).collect();
+		Ok(Burning::new(steps, self.mask.clone(), self.grid.clone()))
+	}
+
+	// The spanning tree of a recurrent sandpile by the burning bijection, see `GraphSandpile::spanning_tree`.
+	// The tie-breaking order is that of the vertices, i.e. of the cells row by row, with the sink first on the torus and last otherwise.
+	pub fn spanning_tree(&self) -> Result<SpanningTree, SandpileError> {
+		let (x, y) = (self.grid[0].len(), self.grid.len());
+		let parents = self.graph_sandpile().spanning_tree()?;
+		let sink = if self.grid_type.sink_in_grid() {0} else {x*y};
+		let edges = (0..y).map(|i| (0..x).map(|j| {
+			let v = i*x + j;
+			if v == sink || self.mask.as_ref().is_some_and(|mask| !mask[i][j]) {
+				return None;
+			}
+			// The k-th of the parallel edges to the parent lies along the offset which covers k in the stencil order.
+			let (parent, mut k) = parents[v].unwrap();
+			self.neighbourhood.offsets((i, j)).iter().find_map(|&(di, dj, w)| {
+				if Self::offset_target(self.grid_type, self.mask.as_deref(), (x, y), (i, j), (di, dj)) != parent {
+					None
+				} else if k < w {
+					Some((di, dj, k))
+				} else {
+					k -= w;
+					None
+				}
+			})
+		}).collect()).collect();
+		Ok(SpanningTree::new(self.grid_type, self.mask.clone(), edges))
+	}
+
+	// The recurrent sandpile of a spanning tree, the inverse of `spanning_tree`.
+	pub fn from_spanning_tree(neighbourhood: Neighbourhood, tree: &SpanningTree) -> Result<GridSandpile, SandpileError> {
+		let edges = &tree.edges;
+		let (x, y) = (edges.first().map_or(0, |row| row.len()), edges.len());
+		if x == 0 {
+			return Err(SandpileError::EmptyGrid);
+		}
+		let mask = tree.mask.as_deref();
+		if let Some(mask) = mask {
+			mask::verify_mask(mask, (x, y))?;
+		}
+		let graph = Self::graph_with_mask(tree.grid_type, neighbourhood.clone(), mask, (x, y));
+		let mut parents = vec![None; graph.len()];
+		for i in 0..y {
+			for j in 0..x {
+				let v = i*x + j;
+				if mask.is_some_and(|mask| !mask[i][j]) {
+					parents[v] = Some((graph.sink(), 0));
+					continue;
+				}
+				let Some((di, dj, k)) = edges[i][j] else {
+					continue;
+				};
+				// Parallel edges along the offsets before this one come first.
+				let target = |(di, dj)| Self::offset_target(tree.grid_type, mask, (x, y), (i, j), (di, dj));
+				let parent = target((di, dj));
+				let offsets = neighbourhood.offsets((i, j));
+				let Some(n) = offsets.iter().position(|&(oi, oj, w)| (oi, oj) == (di, dj) && k < w) else {
+					return Err(SandpileError::InvalidTree(v));
+				};
+				let before: Cell = offsets[..n].iter().filter(|&&(oi, oj, _)| target((oi, oj)) == parent).map(|&(_, _, w)| w).sum();
+				parents[v] = Some((parent, before + k));
+			}
+		}
+		let config = GraphSandpile::from_spanning_tree(Rc::new(graph), &parents)?.into_config();
+		Ok(Self::from_graph_config(tree.grid_type, neighbourhood, tree.mask.clone(), (x, y), &config))
+	}
+
+	// A uniformly random recurrent sandpile, see `GraphSandpile::random_recurrent`. The same seed gives the same sandpile.
+	pub fn random_recurrent(grid_type: FiniteGridType, neighbourhood: Neighbourhood, (x, y): (usize, usize), seed: u64) -> Result<GridSandpile, SandpileError> {
+		let graph = Self::graph(grid_type, neighbourhood.clone(), (x, y));
+		let config = GraphSandpile::random_recurrent(Rc::new(graph), seed)?.into_config();
+		Ok(Self::from_graph_config(grid_type, neighbourhood, None, (x, y), &config))
+	}
+
+	pub fn random_recurrent_masked(grid_type: FiniteGridType, neighbourhood: Neighbourhood, mask: Rc<Mask>, seed: u64) -> Result<GridSandpile, SandpileError> {
+		let (x, y) = (mask.first().map_or(0, |row| row.len()), mask.len());
+		if x == 0 {
+			return Err(SandpileError::EmptyGrid);
+		}
+		mask::verify_mask(&mask, (x, y))?;
+		let graph = Self::graph_with_mask(grid_type, neighbourhood.clone(), Some(&mask), (x, y));
+		let config = GraphSandpile::random_recurrent(Rc::new(graph), seed)?.into_config();
+		Ok(Self::from_graph_config(grid_type, neighbourhood, Some(mask), (x, y), &config))
+	}
+
+	// All recurrent sandpiles of the grid, see `AllRecurrent`. There are as many of them as the `group_order`,
+	// so this is only feasible for small grids, like 4×4 or 3×6 with the von Neumann neighbourhood.
+	pub fn all_recurrent(grid_type: FiniteGridType, neighbourhood: Neighbourhood, (x, y): (usize, usize)) -> impl Iterator<Item = GridSandpile> {
+		let graph = Self::graph(grid_type, neighbourhood.clone(), (x, y));
+		GraphSandpile::all_recurrent(Rc::new(graph))
+			.map(move |s| Self::from_graph_config(grid_type, neighbourhood.clone(), None, (x, y), s.config()))
+	}
+
+	pub fn all_recurrent_masked(grid_type: FiniteGridType, neighbourhood: Neighbourhood, mask: Rc<Mask>) -> Result<impl Iterator<Item = GridSandpile>, SandpileError> {
+		let (x, y) = (mask.first().map_or(0, |row| row.len()), mask.len());
+		if x == 0 {
+			return Err(SandpileError::EmptyGrid);
+		}
+		mask::verify_mask(&mask, (x, y))?;
+		let graph = Self::graph_with_mask(grid_type, neighbourhood.clone(), Some(&mask), (x, y));
+		Ok(GraphSandpile::all_recurrent(Rc::new(graph))
+			.map(move |s| Self::from_graph_config(grid_type, neighbourhood.clone(), Some(Rc::clone(&mask)), (x, y), s.config())))
+	}
+
+	// The sandpile with the chips of the vertices of `graph`, the sink beyond the grid dropped.
+	// Configurations of graph sandpiles are stable, so there is nothing to topple.
+	fn from_graph_config(grid_type: FiniteGridType, neighbourhood: Neighbourhood, mask: Option<Rc<Mask>>, (x, y): (usize, usize), config: &[Cell]) -> GridSandpile {
+		GridSandpile {
+			grid_type: GridType::Finite(grid_type),
+			neighbourhood,
+			mask,
+			background: None,
+			grid: config.chunks(x).take(y).map(<[Cell]>::to_vec).collect(),
+			last_topple: 0,
+			odometer: None,
+		}
+	}
+
+	// The vertex which gets chips from cell (i, j) along offset (di, dj), see `graph`.
+	fn offset_target(grid_type: FiniteGridType, mask: Option<&Mask>, (x, y): (usize, usize), (i, j): (usize, usize), (di, dj): (isize, isize)) -> usize {
+		let sink = if grid_type.sink_in_grid() {0} else {x*y};
+		match grid_type.neighbour((y, x), (i, j), (di, dj)) {
+			Some((ti, tj)) if mask.is_none_or(|mask| mask[ti][tj]) => ti*x + tj,
+			_ => sink,
+		}
+	}
+
+	fn graph_sandpile(&self) -> GraphSandpile {
+		let (x, y) = (self.grid[0].len(), self.grid.len());
+		let graph = Self::graph_with_mask(self.grid_type, self.neighbourhood.clone(), self.mask.as_deref(), (x, y));
+		let mut config: Vec<_> = self.grid.iter().flatten().copied().collect();
+		config.resize(graph.len(), 0);
+		GraphSandpile::from_config(Rc::new(graph), config).unwrap()
+	}
+
+	// Adds the sandpile to itself until it gets back, starting from the first recurrent multiple.
+	pub fn order_by_addition(&self) -> u64
 	{
-		let mut a = GridSandpile::from_grid(GridType::Finite(self.grid_type), self.neighbourhood, self.grid.clone()).unwrap();
+		let mut start = GridSandpile::from_grid_with_mask(GridType::Finite(self.grid_type), self.neighbourhood.clone(), self.mask.clone(), self.grid.clone()).unwrap();
+		for _ in 0..self.graph_sandpile().tail() {
+			start.add_grid_unchecked(self.grid);
+		}
+		let mut a = start.clone();
 		a.add_grid_unchecked(self.grid);
 		let mut count = 1;
-		while &a.grid != self.grid {
+		while a.grid != start.grid {
 			a.add_grid_unchecked(self.grid);
 			count += 1;
 		}
@@ -485,6 +1241,22 @@ pub enum SandpileError {
 	UnequalDimensions(usize, usize, usize, usize),
 	UnknownSymbol(char),
 	Infinite,
+	EmptyStencil,
+	SelfNeighbour,
+	UnequalGraphs,
+	UnequalLengths(usize, usize),
+	InvalidVertex(usize),
+	AsymmetricGraph(usize, usize),
+	SinkUnreachable(usize),
+	UnequalLattices,
+	UnequalMasks,
+	UnequalBackgrounds,
+	FiniteBackground,
+	UnstableBackground,
+	BelowBackground,
+	NotRecurrent,
+	InvalidTree(usize),
+	UnsupportedGrid,
 }
 
 impl fmt::Display for SandpileError {
@@ -500,6 +1272,26 @@ impl fmt::Display for SandpileError {
 				write!(f, "Incorrect dimensions of sandpile grids: expected {self_x}x{self_y}, got {other_x}x{other_y}."),
 			SandpileError::UnknownSymbol(ch) => write!(f, "Unknown symbol in the text representation of a sandpile: {ch}"),
 			SandpileError::Infinite => write!(f, "Attempted to view infinite sandpile as finite sandpile."),
+			SandpileError::EmptyStencil => write!(f, "Neighbourhood stencil has no neighbours."),
+			SandpileError::SelfNeighbour => write!(f, "Neighbourhood stencil contains the offset (0, 0)."),
+			SandpileError::UnequalGraphs => write!(f, "Adding sandpiles on different graphs."),
+			SandpileError::UnequalLengths(expected, got) =>
+				write!(f, "Incorrect number of vertices in sandpile configuration: expected {expected}, got {got}."),
+			SandpileError::InvalidVertex(v) => write!(f, "Vertex {v} does not exist in the graph."),
+			SandpileError::AsymmetricGraph(v, u) =>
+				write!(f, "Undirected graph has different numbers of edges from {v} to {u} and from {u} to {v}."),
+			SandpileError::SinkUnreachable(v) => write!(f, "There is no path from vertex {v} to the sink."),
+			SandpileError::UnequalLattices => write!(f, "Adding sandpiles on lattices of different types or dimensions."),
+			SandpileError::UnequalMasks => write!(f, "Adding sandpiles on different domains."),
+			SandpileError::UnequalBackgrounds => write!(f, "Adding sandpiles on different backgrounds."),
+			SandpileError::FiniteBackground => write!(f, "Only the infinite grid may have a background."),
+			SandpileError::UnstableBackground => write!(f, "The background has cells which topple."),
+			SandpileError::BelowBackground => write!(f, "Sandpiles on a background are added by their chips above it, and the sum has a negative number of chips in some cell."),
+			SandpileError::NotRecurrent => write!(f, "The sandpile is not recurrent."),
+			SandpileError::InvalidTree(v) =>
+				write!(f, "The edges do not form a spanning tree directed to the sink: vertex {v} has no valid edge to its parent or no path to the sink along them."),
+			SandpileError::UnsupportedGrid =>
+				write!(f, "Only rectangles with the sink all around, no mask, and the von Neumann or Moore neighbourhood are supported."),
 		}
 	}
 }
@@ -517,12 +1309,56 @@ impl SandpileError {
 	}
 }
 
-pub fn png(grid: &Grid, fname: &str, colors: &Vec<[u8; 4]>) -> io::Result<()> {
+// Heights beyond the palette start it over, as with stencils of large weights.
+pub fn png(grid: &Grid, fname: &str, colors: &[[u8; 4]]) -> io::Result<()> {
 	let mut pixels = vec![0; grid.len() * grid[0].len() * 4];
 	let mut p = 0;
 	for row in grid {
 		for el in row {
-			pixels[p..p+4].copy_from_slice(&colors[*el as usize]);
+			pixels[p..p+4].copy_from_slice(&colors[*el as usize % colors.len()]);
+			p += 4;
+		}
+	}
+	repng::encode(File::create(fname)?, grid[0].len() as u32, grid.len() as u32, &pixels)
+}
+
+// Heights are drawn relative to the background, and cells below it take colours from the end of the palette.
+pub fn png_relative(grid: &Grid, background: &Grid, fname: &str, colors: &[[u8; 4]]) -> io::Result<()> {
+	let mut pixels = vec![0; grid.len() * grid[0].len() * 4];
+	let mut p = 0;
+	for (row, bg_row) in grid.iter().zip(background) {
+		for (&el, &bg) in row.iter().zip(bg_row) {
+			let n = if el >= bg {(el - bg) as usize} else {colors.len() - (bg - el) as usize % colors.len()};
+			pixels[p..p+4].copy_from_slice(&colors[n % colors.len()]);
+			p += 4;
+		}
+	}
+	repng::encode(File::create(fname)?, grid[0].len() as u32, grid.len() as u32, &pixels)
+}
+
+// The colours are spread evenly from no topplings to the most topplings of a cell, and the numbers between them are interpolated.
+pub fn png_odometer(odometer: &Grid, fname: &str, colors: &[[u8; 4]]) -> io::Result<()> {
+	if colors.len() < 2 {
+		return Err(io::Error::new(io::ErrorKind::InvalidInput, "the odometer needs at least 2 colours"))
+	}
+	let max = odometer.iter().flatten().copied().max().unwrap_or(0).max(1);
+	let mut pixels = Vec::with_capacity(odometer.len() * odometer[0].len() * 4);
+	for &n in odometer.iter().flatten() {
+		let t = n as f64 / max as f64 * (colors.len() - 1) as f64;
+		let k = (t as usize).min(colors.len() - 2);
+		let f = t - k as f64;
+		pixels.extend((0..4).map(|c| (colors[k][c] as f64 * (1.0 - f) + colors[k+1][c] as f64 * f).round() as u8));
+	}
+	repng::encode(File::create(fname)?, odometer[0].len() as u32, odometer.len() as u32, &pixels)
+}
+
+// Cells outside the mask are drawn with `masked_color`.
+pub fn png_masked(grid: &Grid, mask: &Mask, fname: &str, colors: &[[u8; 4]], masked_color: [u8; 4]) -> io::Result<()> {
+	let mut pixels = vec![0; grid.len() * grid[0].len() * 4];
+	let mut p = 0;
+	for (row, mask_row) in grid.iter().zip(mask) {
+		for (el, &present) in row.iter().zip(mask_row) {
+			pixels[p..p+4].copy_from_slice(if present {&colors[*el as usize % colors.len()]} else {&masked_color});
 			p += 4;
 		}
 	}
@@ -533,6 +1369,30 @@ pub fn png(grid: &Grid, fname: &str, colors: &Vec<[u8; 4]>) -> io::Result<()> {
 mod tests {
 	use super::*;
 
+	#[test]
+	fn png_heavy_stencil() {
+		// Stable heights up to 11 with a palette of 8 colours.
+		let stencil = Neighbourhood::custom(vec![(-1, 0, 3), (1, 0, 3), (0, -1, 3), (0, 1, 3)]).unwrap();
+		let s = FiniteGridSandpile::neutral(FiniteGridType::Rectangular, stencil, (5, 4));
+		assert!(s.grid.iter().flatten().any(|&n| n >= 8));
+		let colors: Vec<[u8; 4]> = (0..8).map(|k| [k * 30, 0, 0, 255]).collect();
+		let fname = std::env::temp_dir().join("sandpile-png-heavy-stencil.png");
+		let fname = fname.to_str().unwrap();
+		png(&s.grid, fname, &colors).unwrap();
+		png_masked(&s.grid, &vec![vec![true; 5]; 4], fname, &colors, [0; 4]).unwrap();
+		std::fs::remove_file(fname).unwrap();
+	}
+
+	#[test]
+	fn png_odometer_colors() {
+		let fname = std::env::temp_dir().join("sandpile-png-odometer-colors.png");
+		let fname = fname.to_str().unwrap();
+		let err = png_odometer(&vec![vec![0, 3]], fname, &[[0; 4]]).unwrap_err();
+		assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+		png_odometer(&vec![vec![0, 3]], fname, &[[0; 4], [255; 4]]).unwrap();
+		std::fs::remove_file(fname).unwrap();
+	}
+
 	#[test]
 	fn id_rectangular() {
 		let s = FiniteGridSandpile::neutral(FiniteGridType::Rectangular, Neighbourhood::VonNeumann, (3, 2));
@@ -577,8 +1437,11 @@ mod tests {
 		let s3 = GridSandpile {
 			grid_type: GridType::Finite(FiniteGridType::Rectangular),
 			neighbourhood: Neighbourhood::VonNeumann,
+			mask: None,
+			background: None,
 			grid: vec![vec![p; x]; y],
 			last_topple: 0,
+			odometer: None,
 		};
 		s2.add(&s3).unwrap();
 		assert_eq!(s1, s2);
@@ -612,8 +1475,11 @@ mod tests {
 		let mut s = GridSandpile {
 			grid_type: GridType::Infinite(0, 0),
 			neighbourhood: Neighbourhood::VonNeumann,
+			mask: None,
+			background: None,
 			grid: vec![vec![16]],
 			last_topple: 0,
+			odometer: None,
 		};
 		s.topple();
 		let s2 = GridSandpile::from_grid(GridType::Infinite(0, 0), Neighbourhood::VonNeumann, vec![
@@ -631,38 +1497,122 @@ mod tests {
 		let mut s = GridSandpile {
 			grid_type: GridType::Infinite(0, 0),
 			neighbourhood: Neighbourhood::Moore,
+			mask: None,
+			background: None,
 			grid: vec![vec![500]],
 			last_topple: 0,
+			odometer: None,
 		};
 		s.topple();
 		let s2 = GridSandpile::from_grid(GridType::Infinite(0, 0), Neighbourhood::Moore, vec![vec![500]]).unwrap();
 		assert_eq!(s.grid, s2.grid);
 	}
 	
+	#[test]
+	fn infinite_growth() {
+		// The grid grows ahead and is cut down to the former cells and the neighbourhoods of the toppled ones,
+		// as the symmetric toppling stores them.
+		let stencil = Neighbourhood::custom(vec![(-1, 0, 1), (1, 0, 1), (0, -1, 1), (0, 1, 1)]).unwrap();
+		for grid in [vec![vec![700]], vec![vec![0, 0, 0, 0], vec![0, 0, 0, 300], vec![0, 0, 0, 0]], vec![vec![5; 9]; 2]] {
+			let s = GridSandpile::from_grid(GridType::Infinite(0, 0), stencil.clone(), grid.clone()).unwrap();
+			let s2 = GridSandpile::from_grid(GridType::Infinite(0, 0), Neighbourhood::VonNeumann, grid).unwrap();
+			assert_eq!((&s.grid, s.grid_type, &s.odometer), (&s2.grid, s2.grid_type, &s2.odometer));
+		}
+	}
+
+	#[test]
+	fn infinite_delta00_doubling() {
+		// The pile of 300 chips from those of 1, 2, 4, …, 256 chips.
+		let one = GridSandpile::from_grid(GridType::Infinite(0, 0), Neighbourhood::VonNeumann, vec![vec![1]]).unwrap();
+		let mut powers = Vec::new();
+		let s = one.multiple_with_powers(300, |n, p| powers.push((n, p.grid.clone(), p.grid_type)));
+		assert_eq!(powers.len(), 9);
+		for (n, grid, grid_type) in powers {
+			let p = GridSandpile::from_grid(GridType::Infinite(0, 0), Neighbourhood::VonNeumann, vec![vec![1 << n]]).unwrap();
+			assert_eq!((grid, grid_type), (p.grid, p.grid_type));
+		}
+		let s2 = GridSandpile::from_grid(GridType::Infinite(0, 0), Neighbourhood::VonNeumann, vec![vec![300]]).unwrap();
+		assert_eq!((&s.grid, s.grid_type, &s.odometer), (&s2.grid, s2.grid_type, &s2.odometer));
+	}
+
 	#[test]
 	fn infinite_delta00_optimized() {
 		let mut s = GridSandpile {
 			grid_type: GridType::Infinite(0, 0),
 			neighbourhood: Neighbourhood::VonNeumann,
+			mask: None,
+			background: None,
 			grid: vec![vec![200]],
 			last_topple: 0,
+			odometer: None,
 		};
 		s.topple();
 		let s2 = GridSandpile::from_grid(GridType::Infinite(0, 0), Neighbourhood::VonNeumann, vec![vec![200]]).unwrap();
 		assert_eq!(s.grid, s2.grid);
 		assert_eq!(s.last_topple, s2.last_topple);
+		assert_eq!(s.odometer, s2.odometer);
 		let mut s = GridSandpile {
 			grid_type: GridType::Infinite(0, 0),
 			neighbourhood: Neighbourhood::Moore,
+			mask: None,
+			background: None,
 			grid: vec![vec![200]],
 			last_topple: 0,
+			odometer: None,
 		};
 		s.topple();
 		let s2 = GridSandpile::from_grid(GridType::Infinite(0, 0), Neighbourhood::Moore, vec![vec![200]]).unwrap();
 		assert_eq!(s.grid, s2.grid);
 		assert_eq!(s.last_topple, s2.last_topple);
+		assert_eq!(s.odometer, s2.odometer);
+	}
+
+	#[test]
+	fn odometer() {
+		let s = GridSandpile::from_grid(GridType::Finite(FiniteGridType::Rectangular), Neighbourhood::VonNeumann, vec![vec![9, 3]]).unwrap();
+		assert_eq!(s.odometer(), Some(&vec![vec![2, 1]]));
+		// The optimized identities count the topplings like the general one.
+		for (x, y) in [(6, 6), (10, 8)] {
+			let e = FiniteGridSandpile::neutral(FiniteGridType::Rectangular, Neighbourhood::VonNeumann, (x, y));
+			let e2 = FiniteGridSandpile::neutral_masked(FiniteGridType::Rectangular, Neighbourhood::VonNeumann, Rc::new(vec![vec![true; x]; y])).unwrap();
+			assert_eq!(e.odometer, e2.odometer);
+			assert_eq!(e.last_topple, e2.last_topple);
+		}
+		// Multiplying by doubling and adding gives the odometer of stabilizing the multiple at once.
+		let s = GridSandpile::from_grid(GridType::Finite(FiniteGridType::Rectangular), Neighbourhood::VonNeumann, vec![vec![3, 2, 3], vec![1, 3, 3]]).unwrap();
+		let s11 = GridSandpile::from_grid(GridType::Finite(FiniteGridType::Rectangular), Neighbourhood::VonNeumann, vec![vec![33, 22, 33], vec![11, 33, 33]]).unwrap();
+		assert_eq!(s.multiple(11).odometer, s11.odometer);
+		assert_eq!(s.multiple(1).odometer, Some(vec![vec![0; 3]; 2]));
+		let s = GridSandpile::from_grid(GridType::Infinite(0, 0), Neighbourhood::VonNeumann, vec![vec![3, 0, 0, 2]]).unwrap();
+		let s6 = GridSandpile::from_grid(GridType::Infinite(0, 0), Neighbourhood::VonNeumann, vec![vec![18, 0, 0, 12]]).unwrap();
+		let s6m = s.multiple(6);
+		assert_eq!((&s6m.grid, s6m.grid_type, &s6m.odometer), (&s6.grid, s6.grid_type, &s6.odometer));
 	}
 	
+	#[test]
+	fn least_action() {
+		// Heavy rectangles fire a lower bound of the odometer first, and end like the ordinary toppling.
+		let mut rng = SplitMix64::new(23);
+		for neighbourhood in [Neighbourhood::VonNeumann, Neighbourhood::Moore] {
+			for (x, y, n) in [(1, 1, 1000), (7, 3, 100), (21, 17, 200), (33, 30, 20)] {
+				let grid: Grid = (0..y).map(|_| (0..x).map(|_| rng.below(2 * n)).collect()).collect();
+				let s = GridSandpile::from_grid(GridType::Finite(FiniteGridType::Rectangular), neighbourhood.clone(), grid.clone()).unwrap();
+				let mut t = GridSandpile {
+					grid_type: GridType::Finite(FiniteGridType::Rectangular),
+					neighbourhood: neighbourhood.clone(),
+					mask: None,
+					background: None,
+					grid,
+					last_topple: 0,
+					odometer: None,
+				};
+				assert!(t.clone().least_action_fire().is_some());
+				t.topple_unoptimized();
+				assert_eq!((&s.grid, &s.odometer, s.last_topple), (&t.grid, &t.odometer, t.last_topple), "{neighbourhood:?} {x}×{y}");
+			}
+		}
+	}
+
 	#[test]
 	fn from_string() {
 		let st = "&. \n:.:\n";
@@ -694,10 +1644,115 @@ mod tests {
 		assert_eq!(s1.last_topple(), 9);
 	}
 	
+	#[test]
+	fn all_recurrent() {
+		// As many as spanning trees, all distinct and recurrent, for undirected grids and digraphs alike.
+		for (grid_type, neighbourhood, (x, y)) in [
+			(FiniteGridType::Rectangular, Neighbourhood::VonNeumann, (3, 2)),
+			(FiniteGridType::Toroidal, Neighbourhood::VonNeumann, (3, 2)),
+			(FiniteGridType::Rectangular, Neighbourhood::Moore, (2, 2)),
+			(FiniteGridType::Box([Boundary::Sink, Boundary::Periodic, Boundary::Reflecting, Boundary::Sink]), Neighbourhood::VonNeumann, (3, 2)),
+		] {
+			let all: Vec<_> = FiniteGridSandpile::all_recurrent(grid_type, neighbourhood.clone(), (x, y)).collect();
+			assert_eq!(BigUint::from(all.len()), FiniteGridSandpile::group_order(grid_type, neighbourhood.clone(), (x, y)), "{grid_type:?} {neighbourhood:?}");
+			assert!(all.iter().all(|s| FiniteGridSandpile::try_from(s).unwrap().graph_sandpile().is_recurrent()));
+			let cells: Vec<_> = all.into_iter().map(|s| s.into_grid().concat()).collect();
+			assert!(cells.is_sorted_by(|a, b| a < b));
+		}
+		// On the 3×1 rectangle, with no chips at the left end, the fire has to come from the right through the middle.
+		let all: Vec<_> = FiniteGridSandpile::all_recurrent(FiniteGridType::Rectangular, Neighbourhood::VonNeumann, (3, 1)).take(4).map(GridSandpile::into_grid).collect();
+		assert_eq!(all, [[[0, 1, 1]], [[0, 1, 2]], [[0, 1, 3]], [[0, 2, 0]]]);
+	}
+
+	#[test]
+	fn random_recurrent() {
+		// Every recurrent sandpile appears about equally often, parallel edges of the 2-high torus included.
+		for (grid_type, (x, y)) in [(FiniteGridType::Rectangular, (2, 2)), (FiniteGridType::Toroidal, (3, 2))] {
+			let order = FiniteGridSandpile::group_order(grid_type, Neighbourhood::VonNeumann, (x, y));
+			let order: usize = order.try_into().unwrap();
+			let mut counts = std::collections::HashMap::new();
+			for seed in 0..40 * order as u64 {
+				let s = FiniteGridSandpile::random_recurrent(grid_type, Neighbourhood::VonNeumann, (x, y), seed).unwrap();
+				assert!(FiniteGridSandpile::try_from(&s).unwrap().burning().unwrap().is_recurrent());
+				*counts.entry(s.into_grid()).or_insert(0) += 1;
+			}
+			assert_eq!(counts.len(), order, "{grid_type:?}");
+			assert!(counts.values().all(|&n| (10..80).contains(&n)), "{grid_type:?} {counts:?}");
+		}
+		let random = |seed| FiniteGridSandpile::random_recurrent(FiniteGridType::Rectangular, Neighbourhood::Moore, (10, 10), seed).unwrap();
+		assert_eq!(random(1), random(1));
+		assert_ne!(random(1), random(2));
+	}
+
+	#[test]
+	fn group_operations() {
+		let finite = GridType::Finite(FiniteGridType::Rectangular);
+		let a = GridSandpile::from_grid(finite, Neighbourhood::VonNeumann, vec![vec![3, 3, 3], vec![3, 3, 3]]).unwrap();
+		let b = GridSandpile::from_grid(finite, Neighbourhood::VonNeumann, vec![vec![1, 2, 3], vec![3, 0, 1]]).unwrap();
+		let id = FiniteGridSandpile::neutral(FiniteGridType::Rectangular, Neighbourhood::VonNeumann, (3, 2));
+		let mut sum = a.clone();
+		for k in 1..20 {
+			assert_eq!(&a * k, sum);
+			sum.add(&a).unwrap();
+		}
+		assert_eq!(&a * 7, id);
+		assert_eq!(a.multiple(0), GridSandpile::from_grid(finite, Neighbourhood::VonNeumann, vec![vec![0; 3]; 2]).unwrap());
+		assert_eq!(&(&a - &b) + &b, a);
+		assert_eq!(&a + &(-&a), id);
+		assert_eq!(-(-a.clone()), a);
+		assert_eq!(a.clone() * 3 - a.clone(), a * 2);
+		// Infinite sandpiles can be added and multiplied, but not negated.
+		let c = GridSandpile::from_grid(GridType::Infinite(0, 0), Neighbourhood::VonNeumann, vec![vec![3]]).unwrap();
+		assert_eq!(&c * 5, &(&c * 2) + &(&c * 3));
+		assert!(c.checked_neg().is_err());
+		assert!(c.checked_sub(&c).is_err());
+		assert!(b.checked_add(&c).is_err());
+	}
+
+	#[test]
+	fn equivalence() {
+		let signed = |g: Grid| -> Vec<Vec<BigInt>> {g.into_iter().map(|row| row.into_iter().map(BigInt::from).collect()).collect()};
+		let (t, nbhd) = (FiniteGridType::Rectangular, Neighbourhood::VonNeumann);
+		// A configuration is equivalent to its stabilization, and the burning configuration to zero.
+		let a = vec![vec![8, 0, 17], vec![5, 9, 2]];
+		let s = GridSandpile::from_grid(GridType::Finite(t), nbhd.clone(), a.clone()).unwrap().into_grid();
+		assert!(FiniteGridSandpile::equivalent(t, nbhd.clone(), &signed(a.clone()), &signed(s.clone())).unwrap());
+		let zero = vec![vec![BigInt::ZERO; 3]; 2];
+		let burn = signed(FiniteGridSandpile::burn(t, nbhd.clone(), (3, 2)).into_grid());
+		assert!(FiniteGridSandpile::equivalent(t, nbhd.clone(), &burn, &zero).unwrap());
+		let mut one = zero.clone();
+		one[0][1] = BigInt::from(1);
+		assert!(!FiniteGridSandpile::equivalent(t, nbhd.clone(), &one, &zero).unwrap());
+		// Negative and huge configurations: the group of the 2×2 rectangle is Z_8 × Z_24, so 24 chips everywhere are trivial, and -1 is 23.
+		let huge = vec![vec![BigInt::from(24) * BigInt::from(u128::MAX); 2]; 2];
+		assert!(FiniteGridSandpile::equivalent(t, nbhd.clone(), &huge, &vec![vec![BigInt::ZERO; 2]; 2]).unwrap());
+		assert!(FiniteGridSandpile::equivalent(t, nbhd.clone(), &vec![vec![BigInt::from(-1); 2]; 2], &vec![vec![BigInt::from(23); 2]; 2]).unwrap());
+		// The sink's chips on the torus and the chips outside the mask don't matter.
+		let mut sink = zero.clone();
+		sink[0][0] = BigInt::from(5);
+		assert!(FiniteGridSandpile::equivalent(FiniteGridType::Toroidal, nbhd.clone(), &sink, &zero).unwrap());
+		let mask = mask_from_string("x..\n...").unwrap();
+		assert!(FiniteGridSandpile::equivalent_masked(t, nbhd.clone(), &mask, &sink, &zero).unwrap());
+		assert!(FiniteGridSandpile::equivalent(t, nbhd, &sink, &vec![vec![BigInt::ZERO; 2]; 2]).is_err());
+	}
+
 	#[test]
 	fn order() {
 		let s = GridSandpile::from_grid(GridType::Finite(FiniteGridType::Rectangular), Neighbourhood::VonNeumann, vec![vec![3, 3, 3], vec![3, 3, 3]]).unwrap();
-		assert_eq!(FiniteGridSandpile::try_from(&s).unwrap().order(), 7);
+		assert_eq!(FiniteGridSandpile::try_from(&s).unwrap().order_by_addition(), 7);
+		assert_eq!(FiniteGridSandpile::try_from(&s).unwrap().order(), BigUint::from(7u8));
+		// Non-recurrent sandpiles have orders too: the group of the single cell is Z_4, generated by one chip.
+		let s = GridSandpile::from_grid(GridType::Finite(FiniteGridType::Rectangular), Neighbourhood::VonNeumann, vec![vec![1]]).unwrap();
+		assert_eq!(FiniteGridSandpile::try_from(&s).unwrap().order(), BigUint::from(4u8));
+		let s = GridSandpile::from_grid(GridType::Finite(FiniteGridType::Rectangular), Neighbourhood::Moore, vec![vec![7; 3]; 2]).unwrap();
+		let a = FiniteGridSandpile::try_from(&s).unwrap();
+		assert_eq!(a.order(), BigUint::from(a.order_by_addition()));
+		// The first 19 multiples of one chip in the corner are not recurrent.
+		let s = GridSandpile::from_grid(GridType::Finite(FiniteGridType::Rectangular), Neighbourhood::VonNeumann, vec![vec![1, 0, 0], vec![0, 0, 0]]).unwrap();
+		let a = FiniteGridSandpile::try_from(&s).unwrap();
+		let (tail, cycle) = a.rho();
+		assert_eq!(tail, 19);
+		assert_eq!(cycle, BigUint::from(a.order_by_addition()));
 	}
 	
 	#[test]
@@ -714,4 +1769,243 @@ mod tests {
 			GridSandpile::from_grid(GridType::Finite(FiniteGridType::Rectangular), Neighbourhood::Moore, vec![vec![1, 1, 1], vec![1, 1, 1], vec![1, 1, 1]]).unwrap()
 		);
 	}
+	
+	#[test]
+	fn triangular_hexagonal() {
+		assert_eq!(
+			GridSandpile::from_grid(GridType::Finite(FiniteGridType::Rectangular), Neighbourhood::Triangular, vec![vec![0, 0, 0], vec![0, 6, 0], vec![0, 0, 0]]).unwrap().into_grid(),
+			[[0, 1, 1], [1, 0, 1], [0, 1, 1]]
+		);
+		assert_eq!(
+			GridSandpile::from_grid(GridType::Finite(FiniteGridType::Rectangular), Neighbourhood::Hexagonal, vec![vec![0, 0, 0], vec![0, 3, 0], vec![0, 0, 0], vec![0, 0, 0]]).unwrap().into_grid(),
+			[[0, 1, 0], [1, 0, 1], [0, 0, 0], [0, 0, 0]]
+		);
+		let s = GridSandpile::from_grid(GridType::Infinite(0, 0), Neighbourhood::Hexagonal, vec![vec![3]]).unwrap();
+		assert_eq!(s.grid_type(), GridType::Infinite(1, 1));
+		assert_eq!(s.into_grid(), [[0, 1, 0], [1, 0, 1], [0, 0, 0]]);
+	}
+	
+	#[test]
+	fn triangular_hexagonal_group() {
+		for grid_type in [FiniteGridType::Rectangular, FiniteGridType::Toroidal] {
+			for neighbourhood in [Neighbourhood::Triangular, Neighbourhood::Hexagonal] {
+				let e = FiniteGridSandpile::neutral(grid_type, neighbourhood.clone(), (5, 4));
+				let mut e2 = e.clone();
+				e2.add(&e).unwrap();
+				assert_eq!(e, e2);
+				let mut b = FiniteGridSandpile::burn(grid_type, neighbourhood.clone(), (5, 4));
+				b.add(&e).unwrap();
+				assert_eq!(b, e);
+				let g = FiniteGridSandpile::graph(grid_type, neighbourhood, (5, 4));
+				for v in 0..g.len() {
+					for &(u, m) in g.edges(v) {
+						assert_eq!(g.multiplicity(u, v), m);
+					}
+				}
+			}
+		}
+	}
+	
+	#[test]
+	fn custom_stencil() {
+		let vn = Neighbourhood::custom(vec![(1, 0, 1), (-1, 0, 1), (0, 1, 1), (0, -1, 1)]).unwrap();
+		for grid_type in [FiniteGridType::Rectangular, FiniteGridType::Toroidal] {
+			assert_eq!(
+				FiniteGridSandpile::neutral(grid_type, vn.clone(), (7, 5)).into_grid(),
+				FiniteGridSandpile::neutral(grid_type, Neighbourhood::VonNeumann, (7, 5)).into_grid()
+			);
+			assert_eq!(
+				FiniteGridSandpile::burn(grid_type, vn.clone(), (7, 5)).into_grid(),
+				FiniteGridSandpile::burn(grid_type, Neighbourhood::VonNeumann, (7, 5)).into_grid()
+			);
+		}
+		assert!(matches!(Neighbourhood::custom(vec![(0, 0, 1)]), Err(SandpileError::SelfNeighbour)));
+		assert!(matches!(Neighbourhood::custom(vec![(0, 1, 0)]), Err(SandpileError::EmptyStencil)));
+		let knight = Neighbourhood::custom(vec![(1, 2, 1), (2, 1, 1), (-1, 2, 1), (-2, 1, 1), (1, -2, 1), (2, -1, 1), (-1, -2, 1), (-2, -1, 1)]).unwrap();
+		let s = GridSandpile::from_grid(GridType::Infinite(0, 0), knight, vec![vec![8]]).unwrap();
+		assert_eq!(s.grid_type(), GridType::Infinite(2, 2));
+		assert_eq!(s.into_grid(), [
+			[0, 1, 0, 1, 0],
+			[1, 0, 0, 0, 1],
+			[0, 0, 0, 0, 0],
+			[1, 0, 0, 0, 1],
+			[0, 1, 0, 1, 0],
+		]);
+	}
+	
+	#[test]
+	fn weighted_stencil() {
+		let anisotropic = Neighbourhood::custom(vec![(0, 1, 2), (0, -1, 2), (1, 0, 1), (-1, 0, 1)]).unwrap();
+		assert_eq!(anisotropic.neighbours(), 6);
+		let skew = Neighbourhood::custom(vec![(0, 1, 1), (1, 0, 2), (-1, -1, 1)]).unwrap();
+		assert!(!skew.is_symmetric());
+		for neighbourhood in [anisotropic, skew] {
+			for grid_type in [FiniteGridType::Rectangular, FiniteGridType::Toroidal] {
+				let e = FiniteGridSandpile::neutral(grid_type, neighbourhood.clone(), (4, 3));
+				let graph = Rc::new(FiniteGridSandpile::graph(grid_type, neighbourhood.clone(), (4, 3)));
+				assert_eq!(&GraphSandpile::neutral(graph).config()[..12], e.clone().into_grid().concat());
+				let mut b = FiniteGridSandpile::burn(grid_type, neighbourhood.clone(), (4, 3));
+				b.add(&e).unwrap();
+				assert_eq!(b, e);
+			}
+		}
+	}
+	
+	#[test]
+	fn cylinder_mobius_klein() {
+		// The 3×1 cylinder is a triangle with two edges from every vertex to the sink.
+		let triangle = Rc::new(Graph::undirected(vec![
+			vec![(1, 1), (2, 1), (3, 2)],
+			vec![(0, 1), (2, 1), (3, 2)],
+			vec![(0, 1), (1, 1), (3, 2)],
+			vec![(0, 2), (1, 2), (2, 2)],
+		], 3).unwrap());
+		assert_eq!(
+			FiniteGridSandpile::neutral(FiniteGridType::Cylindrical, Neighbourhood::VonNeumann, (3, 1)).into_grid().concat(),
+			GraphSandpile::neutral(triangle).config()[..3]
+		);
+		// In the 2×2 Möbius strip, every cell is adjacent to the three others and to the sink.
+		let k4 = Rc::new(FiniteGridSandpile::graph(FiniteGridType::Mobius, Neighbourhood::VonNeumann, (2, 2)));
+		for v in 0..4 {
+			assert_eq!(k4.edges(v).iter().filter(|&&(u, m)| u != v && m == 1).count(), 4);
+			assert_eq!(k4.multiplicity(v, 4), 1);
+		}
+		for grid_type in [FiniteGridType::Cylindrical, FiniteGridType::Mobius, FiniteGridType::Klein] {
+			for neighbourhood in [Neighbourhood::VonNeumann, Neighbourhood::Moore, Neighbourhood::Hexagonal] {
+				assert!(FiniteGridSandpile::is_symmetric(grid_type, &neighbourhood, (5, 4)));
+				let e = FiniteGridSandpile::neutral(grid_type, neighbourhood.clone(), (5, 4));
+				let mut e2 = e.clone();
+				e2.add(&e).unwrap();
+				assert_eq!(e, e2);
+				let mut b = FiniteGridSandpile::burn(grid_type, neighbourhood.clone(), (5, 4));
+				b.add(&e).unwrap();
+				assert_eq!(b, e);
+			}
+		}
+		assert!(!FiniteGridSandpile::is_symmetric(FiniteGridType::Klein, &Neighbourhood::Triangular, (5, 4)));
+		assert!(!FiniteGridSandpile::is_symmetric(FiniteGridType::Toroidal, &Neighbourhood::Hexagonal, (5, 3)));
+		// The Klein bottle differs from the torus only by the flip.
+		assert_ne!(
+			FiniteGridSandpile::neutral(FiniteGridType::Klein, Neighbourhood::VonNeumann, (5, 4)),
+			FiniteGridSandpile::neutral(FiniteGridType::Toroidal, Neighbourhood::VonNeumann, (5, 4))
+		);
+	}
+	
+	#[test]
+	fn box_boundaries() {
+		// A quadrant of the symmetric identity is the identity of the quadrant with reflecting sides.
+		let quadrant = FiniteGridType::Box([Boundary::Reflecting, Boundary::Sink, Boundary::Sink, Boundary::Reflecting]);
+		let full = FiniteGridSandpile::neutral(FiniteGridType::Rectangular, Neighbourhood::VonNeumann, (10, 8)).into_grid();
+		let g = FiniteGridSandpile::neutral(quadrant, Neighbourhood::VonNeumann, (5, 4)).into_grid();
+		for i in 0..4 {
+			assert_eq!(g[i], full[i+4][5..]);
+		}
+		let sides = [
+			[Boundary::Sink; 4],
+			[Boundary::Periodic; 4],
+			[Boundary::Sink, Boundary::Periodic, Boundary::Sink, Boundary::Periodic],
+		];
+		let types = [FiniteGridType::Rectangular, FiniteGridType::Toroidal, FiniteGridType::Cylindrical];
+		for (sides, grid_type) in sides.into_iter().zip(types) {
+			assert_eq!(
+				FiniteGridSandpile::neutral(FiniteGridType::Box(sides), Neighbourhood::Moore, (5, 4)).into_grid(),
+				FiniteGridSandpile::neutral(grid_type, Neighbourhood::Moore, (5, 4)).into_grid()
+			);
+		}
+		for sides in [
+			[Boundary::Reflecting, Boundary::Periodic, Boundary::Sink, Boundary::Periodic],
+			[Boundary::Reflecting; 4],
+			[Boundary::Periodic, Boundary::Reflecting, Boundary::Sink, Boundary::Sink],
+		] {
+			let grid_type = FiniteGridType::Box(sides);
+			assert_eq!(grid_type.is_directed(), sides[0] == Boundary::Periodic);
+			let e = FiniteGridSandpile::neutral(grid_type, Neighbourhood::VonNeumann, (5, 4));
+			let mut e2 = e.clone();
+			e2.add(&e).unwrap();
+			assert_eq!(e, e2);
+			let mut b = FiniteGridSandpile::burn(grid_type, Neighbourhood::VonNeumann, (5, 4));
+			b.add(&e).unwrap();
+			assert_eq!(b, e);
+		}
+		// A reflecting side makes a loop at every cell next to it.
+		let square = FiniteGridSandpile::graph(FiniteGridType::Box([Boundary::Reflecting; 4]), Neighbourhood::VonNeumann, (2, 2));
+		assert_eq!(square.sink(), 0);
+		assert_eq!(square.multiplicity(3, 3), 2);
+		assert_eq!(square.multiplicity(3, 1), 1);
+		assert_eq!(square.multiplicity(3, 0), 0);
+	}
+	
+	#[test]
+	fn background() {
+		let s = GridSandpile::from_grid_on_background(GridType::Infinite(0, 0), Neighbourhood::VonNeumann, Rc::new(vec![vec![0]]), vec![vec![100]]).unwrap();
+		let s2 = GridSandpile::from_grid(GridType::Infinite(0, 0), Neighbourhood::VonNeumann, vec![vec![100]]).unwrap();
+		assert_eq!(s.grid, s2.grid);
+		assert_eq!(s.grid_type, s2.grid_type);
+		// Away from the sink, the finite grid behaves as the infinite one.
+		let s = GridSandpile::from_grid_on_background(GridType::Infinite(0, 0), Neighbourhood::VonNeumann, Rc::new(vec![vec![2]]), vec![vec![200]]).unwrap();
+		assert_eq!(s.chips_count(), 200);
+		let GridType::Infinite(oy, ox) = s.grid_type else { panic!() };
+		let mut g = vec![vec![2; 41]; 41];
+		g[20][20] += 200;
+		let f = GridSandpile::from_grid(GridType::Finite(FiniteGridType::Rectangular), Neighbourhood::VonNeumann, g).unwrap().into_grid();
+		assert!(f[0].iter().all(|&el| el == 2));
+		for i in 0..s.grid.len() {
+			for j in 0..s.grid[0].len() {
+				assert_eq!(s.grid[i][j], f[i+20-oy][j+20-ox]);
+			}
+		}
+		// Sandpiles on a periodic background are added by their chips above it.
+		let tile = Rc::new(vec![vec![2, 1], vec![1, 0]]);
+		let mut a = GridSandpile::from_grid_on_background(GridType::Infinite(0, 0), Neighbourhood::VonNeumann, tile.clone(), vec![vec![60]]).unwrap();
+		let b = GridSandpile::from_grid_on_background(GridType::Infinite(1, 0), Neighbourhood::VonNeumann, tile.clone(), vec![vec![0], vec![40]]).unwrap();
+		a.add(&b).unwrap();
+		let c = GridSandpile::from_grid_on_background(GridType::Infinite(0, 0), Neighbourhood::VonNeumann, tile.clone(), vec![vec![100]]).unwrap();
+		assert_eq!(a, c);
+		assert_eq!(a.chips_count(), 100);
+		assert!(a.add(&s).is_err());
+		assert!(GridSandpile::from_grid_on_background(GridType::Infinite(0, 0), Neighbourhood::VonNeumann, Rc::new(vec![vec![4]]), vec![vec![0]]).is_err());
+		assert!(GridSandpile::from_grid_on_background(GridType::Finite(FiniteGridType::Toroidal), Neighbourhood::VonNeumann, tile, vec![vec![0]]).is_err());
+	}
+	
+	#[test]
+	fn infinite_symmetric_optimized() {
+		let mut grids = Vec::new();
+		// Four sources at (±3, ±3), in a frame with extra space at the bottom and the right.
+		let mut g = vec![vec![0; 10]; 9];
+		for (i, j) in [(0, 0), (0, 6), (6, 0), (6, 6)] {
+			g[i][j] = 300;
+		}
+		grids.push((g, (3, 3)));
+		// Reflections in both axes, with the centre between the cells.
+		let mut g = vec![vec![0; 6]; 3];
+		for (i, j) in [(0, 0), (0, 5), (2, 0), (2, 5)] {
+			g[i][j] = 150;
+		}
+		g[1][2] = 40;
+		g[1][3] = 40;
+		grids.push((g, (0, 7)));
+		// A single reflection.
+		grids.push((vec![vec![100, 20, 100], vec![0, 50, 0]], (1, 1)));
+		grids.push((vec![vec![100, 0], vec![7, 60], vec![100, 0]], (2, 0)));
+		for neighbourhood in [Neighbourhood::VonNeumann, Neighbourhood::Moore] {
+			for (g, (oy, ox)) in &grids {
+				let mut s = GridSandpile {
+					grid_type: GridType::Infinite(*oy, *ox),
+					neighbourhood: neighbourhood.clone(),
+					mask: None,
+					background: None,
+					grid: g.clone(),
+					last_topple: 0,
+					odometer: None,
+				};
+				let mut s2 = s.clone();
+				assert!(s.symmetric_infinite_optimized().is_some());
+				s2.topple_unoptimized();
+				assert_eq!(s.grid, s2.grid);
+				assert_eq!(s.grid_type, s2.grid_type);
+				assert_eq!(s.last_topple, s2.last_topple);
+				assert_eq!(s.odometer, s2.odometer);
+			}
+		}
+	}
 }
@@ -1,19 +1,39 @@
 use sandpile::{
 	GridType,
 	FiniteGridType,
+	Boundary,
 	Neighbourhood,
 	GridSandpile,
 	FiniteGridSandpile,
+	LatticeType,
+	LatticeSandpile,
+	Mask,
+	SpanningTree,
+	Tree,
+	BigInt,
 	png,
+	png_masked,
+	png_odometer,
+	png_relative,
+	mask_from_png,
+	mask_from_string,
 };
 
 use std::{
-	io,
+	io::{self, Write},
 	fs,
 	error::Error,
 	convert::TryFrom,
+	rc::Rc,
 };
 
+// Colour of the cells which never burn in the 'burning-png' output.
+const FORBIDDEN_COLOR: [u8; 4] = [255, 0, 0, 255];
+// Size of a cell in the 'tree-png' output, in pixels.
+const TREE_SCALE: usize = 8;
+// Size of a cell in the 'tropical-svg' output, in pixels.
+const TROPICAL_SCALE: usize = 8;
+
 fn main() {
 	if let Err(e) = (|| {
 		let config = Config::new(&mut std::env::args())?;
@@ -24,28 +44,59 @@ fn main() {
 }
 
 fn run(mut config: Config) -> Result<(), Box<dyn Error>> {
+	if let Some((lattice_type, dimensions)) = config.lattice.take() {
+		return run_lattice(config, lattice_type, dimensions)
+	}
+	if let Some(print) = config.enumerate {
+		return enumerate(&config, print)
+	}
+	if config.equiv {
+		return equiv(config)
+	}
 	let (x, y) = config.dimensions;
 	let mut stack = Vec::new();
 	let time = std::time::SystemTime::now();
+	// Outputs about the grid alone need no sandpile.
+	if config.actions.is_empty() {
+		group(&config)?;
+		if config.time {
+			match time.elapsed() {
+				Ok(t) => println!("Total time taken: {}.{} s", t.as_secs(), t.subsec_millis()),
+				Err(e) => eprintln!("{e}"),
+			}
+		}
+		return Ok(())
+	}
 	while let Some(action) = config.actions.pop() {
 		match action {
-			Action::Id => stack.push(FiniteGridSandpile::neutral(config.grid_type.finite()?, config.neighbourhood, config.dimensions)),
-			Action::Burn => stack.push(FiniteGridSandpile::burn(config.grid_type.finite()?, config.neighbourhood, config.dimensions)),
+			Action::Id | Action::Burn | Action::RandomRecurrent(_) | Action::ReadTree => stack.push(generate(&config, action)?),
 			Action::Read => {
 				let mut g = String::new();
 				for _ in 0..y {
 					io::stdin().read_line(&mut g)?;
 				}
-				let a = GridSandpile::from_string(config.grid_type, config.neighbourhood, config.dimensions, g)?;
+				let a = match (config.mask.clone(), config.background.clone()) {
+					(Some(mask), _) => GridSandpile::from_string_masked(config.grid_type, config.neighbourhood.clone(), mask, g)?,
+					(None, Some(background)) => GridSandpile::from_string_on_background(config.grid_type, config.neighbourhood.clone(), background, config.dimensions, g)?,
+					(None, None) => GridSandpile::from_string(config.grid_type, config.neighbourhood.clone(), config.dimensions, g)?,
+				};
 				stack.push(a)
 			},
 			Action::ReadList => {
 				let grid = read_list(x, y)?;
-				let a = GridSandpile::from_grid(config.grid_type, config.neighbourhood, grid)?;
-				stack.push(a)
+				stack.push(from_grid(&config, grid)?)
 			},
 			Action::All(n) => {
-				let a = GridSandpile::from_grid(config.grid_type, config.neighbourhood, vec![vec![n; x]; y])?;
+				stack.push(from_grid(&config, vec![vec![n; x]; y])?)
+			},
+			Action::AllDoubling(n) => {
+				// The powers of two go to out-0.png, out-1.png, etc., as soon as they are stabilized.
+				let one = from_grid(&config, vec![vec![1; x]; y])?;
+				let mut written = Ok(());
+				let a = one.multiple_with_powers(n, |k, p| if let (Ok(()), Some((filename, colors))) = (&written, &config.frames) {
+					written = png_sandpile(p, &frame_name(filename, k as usize), colors).map_err(|e| format!("Can't write to file {}. {e}", frame_name(filename, k as usize)));
+				});
+				written?;
 				stack.push(a)
 			},
 			Action::Inverse => {
@@ -58,6 +109,15 @@ fn run(mut config: Config) -> Result<(), Box<dyn Error>> {
 				a.add(&stack.pop().unwrap())?;
 				stack.push(a)
 			},
+			Action::Sub => {
+				let a = stack.pop().unwrap();
+				let b = stack.pop().unwrap();
+				stack.push(a.checked_sub(&b)?)
+			},
+			Action::Mul(k) => {
+				let a = stack.pop().unwrap();
+				stack.push(a.multiple(k))
+			},
 			Action::Dup => {
 				let a = stack.last().unwrap().clone();
 				stack.push(a);
@@ -79,6 +139,32 @@ fn run(mut config: Config) -> Result<(), Box<dyn Error>> {
 	if config.order {
 		println!("Order: {}", FiniteGridSandpile::try_from(&a)?.order());
 	}
+	if config.rho {
+		let (tail, cycle) = FiniteGridSandpile::try_from(&a)?.rho();
+		println!("Tail: {tail}");
+		println!("Cycle: {cycle}");
+	}
+	if config.order_by_addition {
+		println!("Order by addition: {}", FiniteGridSandpile::try_from(&a)?.order_by_addition());
+	}
+	group(&config)?;
+	let burning = if config.burning || config.burning_png.is_some() {
+		Some(FiniteGridSandpile::try_from(&a)?.burning()?)
+	} else { None };
+	let tree = if config.tree || config.tree_png.is_some() {
+		Some(FiniteGridSandpile::try_from(&a)?.spanning_tree()?)
+	} else { None };
+	if config.tree && let Some(tree) = &tree {
+		print!("{tree}");
+	}
+	if config.burning && let Some(burning) = &burning {
+		println!("Recurrent: {}", burning.is_recurrent());
+		print!("{burning}");
+		if !burning.is_recurrent() {
+			println!("Forbidden subconfiguration:");
+			print!("{}", burning.forbidden_to_string());
+		}
+	}
 	if config.time {
 		match time.elapsed() {
 			Ok(t) => println!("Total time taken: {}.{} s", t.as_secs(), t.subsec_millis()),
@@ -88,9 +174,65 @@ fn run(mut config: Config) -> Result<(), Box<dyn Error>> {
 	if config.out_ascii {
 		print!("{a}");
 	}
+	// Sandpiles which didn't topple, like random recurrent ones, have no odometer, and no cell toppled.
+	let odometer = (config.odometer || config.odometer_png.is_some()).then(|| match a.odometer() {
+		Some(odometer) => odometer.clone(),
+		None => a.background_grid().iter().map(|row| vec![0; row.len()]).collect(),
+	});
+	if config.odometer && let Some(odometer) = &odometer {
+		for row in odometer {
+			println!("{}", row.iter().map(|n| n.to_string()).collect::<Vec<_>>().join(" "));
+		}
+	}
+	if let (Some((mut filename, colors)), Some(odometer)) = (config.odometer_png, &odometer) {
+		while let Err(e) = png_odometer(odometer, &filename, &colors) {
+			eprintln!("Can't write to file {filename}. {e}");
+			eprintln!("Please enter correct name for output file:");
+			filename = String::new();
+			io::stdin().read_line(&mut filename)?;
+			filename = filename.trim().to_string();
+		}
+	}
+	let curve = if config.tropical || config.tropical_svg.is_some() {Some(a.tropical_curve()?)} else { None };
+	if config.tropical && let Some(curve) = &curve {
+		print!("{curve}");
+	}
+	if let (Some(mut filename), Some(curve)) = (config.tropical_svg, &curve) {
+		while let Err(e) = curve.svg(&filename, TROPICAL_SCALE) {
+			eprintln!("Can't write to file {filename}. {e}");
+			eprintln!("Please enter correct name for output file:");
+			filename = String::new();
+			io::stdin().read_line(&mut filename)?;
+			filename = filename.trim().to_string();
+		}
+	}
+	if let (Some(mut filename), Some(tree)) = (config.tree_png, &tree) {
+		while let Err(e) = tree.png(&filename, TREE_SCALE) {
+			eprintln!("Can't write to file {filename}. {e}");
+			eprintln!("Please enter correct name for output file:");
+			filename = String::new();
+			io::stdin().read_line(&mut filename)?;
+			filename = filename.trim().to_string();
+		}
+	}
+	if let (Some((mut filename, colors)), Some(burning)) = (config.burning_png, &burning) {
+		// The burn order goes to out.png, and the forbidden subconfiguration, if any, to out-forbidden.png.
+		let forbidden_name = |filename: &str| match filename.rsplit_once('.') {
+			Some((stem, ext)) => format!("{stem}-forbidden.{ext}"),
+			None => format!("{filename}-forbidden"),
+		};
+		while let Err(e) = burning.png(&filename, &colors, FORBIDDEN_COLOR).and_then(|_| if burning.is_recurrent() {Ok(())} else {
+			burning.png_forbidden(&forbidden_name(&filename), &colors)
+		}) {
+			eprintln!("Can't write to file {filename}. {e}");
+			eprintln!("Please enter correct name for output file:");
+			filename = String::new();
+			io::stdin().read_line(&mut filename)?;
+			filename = filename.trim().to_string();
+		}
+	}
 	if let Some((mut filename, colors)) = config.out_png {
-		let g = a.into_grid();
-		while let Err(e) = png(&g, &filename, &colors) {
+		while let Err(e) = png_sandpile(&a, &filename, &colors) {
 			eprintln!("Can't write to file {filename}. {e}");
 			eprintln!("Please enter correct name for output file:");
 			filename = String::new();
@@ -101,15 +243,288 @@ fn run(mut config: Config) -> Result<(), Box<dyn Error>> {
 	Ok(())
 }
 
+// Cells outside the mask are transparent, and heights are drawn relative to the background.
+fn png_sandpile(a: &GridSandpile, filename: &str, colors: &[[u8; 4]]) -> io::Result<()> {
+	let g = a.clone().into_grid();
+	match (a.mask(), a.background()) {
+		(Some(mask), _) => png_masked(&g, mask, filename, colors, [0; 4]),
+		(None, Some(_)) => png_relative(&g, &a.background_grid(), filename, colors),
+		(None, None) => png(&g, filename, colors),
+	}
+}
+
+// The n-th of a sequence of images: out.png becomes out-n.png.
+fn frame_name(filename: &str, n: usize) -> String {
+	match filename.rsplit_once('.') {
+		Some((stem, ext)) => format!("{stem}-{n}.{ext}"),
+		None => format!("{filename}-{n}"),
+	}
+}
+
+// The order and the structure of the sandpile group, if asked.
+fn group(config: &Config) -> Result<(), Box<dyn Error>> {
+	if config.group_order {
+		let order = match &config.mask {
+			Some(mask) => FiniteGridSandpile::group_order_masked(config.grid_type.finite()?, config.neighbourhood.clone(), mask)?,
+			None => FiniteGridSandpile::group_order(config.grid_type.finite()?, config.neighbourhood.clone(), config.dimensions),
+		};
+		println!("Group order: {order}");
+	}
+	if config.group_structure {
+		let factors = match &config.mask {
+			Some(mask) => FiniteGridSandpile::invariant_factors_masked(config.grid_type.finite()?, config.neighbourhood.clone(), mask)?,
+			None => FiniteGridSandpile::invariant_factors(config.grid_type.finite()?, config.neighbourhood.clone(), config.dimensions),
+		};
+		let factors: Vec<_> = factors.iter().map(|f| format!("Z_{f}")).collect();
+		println!("Group: {}", if factors.is_empty() {"Z_1".to_owned()} else {factors.join(" × ")});
+	}
+	Ok(())
+}
+
+// On a background, the chips are put above it.
+// Goes through all recurrent sandpiles, printing them if asked, and sums up their number, the generating function
+// of their numbers of chips, the level polynomial (the same shifted to start from q^0), and the distribution of heights.
+fn enumerate(config: &Config, print: bool) -> Result<(), Box<dyn Error>> {
+	let all: Box<dyn Iterator<Item = GridSandpile>> = match config.mask.clone() {
+		Some(mask) => Box::new(FiniteGridSandpile::all_recurrent_masked(config.grid_type.finite()?, config.neighbourhood.clone(), mask)?),
+		None => Box::new(FiniteGridSandpile::all_recurrent(config.grid_type.finite()?, config.neighbourhood.clone(), config.dimensions)),
+	};
+	// Cells outside the mask and the sink on the torus have no heights.
+	let (x, y) = config.dimensions;
+	let sink_in_grid = config.grid_type.finite()?.sink_in_grid();
+	let counted: Vec<Vec<bool>> = (0..y).map(|i| (0..x).map(|j| {
+		config.mask.as_ref().is_none_or(|mask| mask[i][j]) && !(sink_in_grid && (i, j) == (0, 0))
+	}).collect()).collect();
+	let mut out = io::BufWriter::new(io::stdout().lock());
+	let mut count = 0u64;
+	let mut by_chips: Vec<u64> = Vec::new();
+	let mut heights: Vec<u64> = Vec::new();
+	for a in all {
+		if print {
+			writeln!(out, "{a}")?;
+		}
+		count += 1;
+		let mut chips = 0;
+		for (row, counted_row) in a.into_grid().iter().zip(&counted) {
+			for (&h, &counted) in row.iter().zip(counted_row) {
+				if !counted {
+					continue;
+				}
+				let h = h as usize;
+				if heights.len() <= h {
+					heights.resize(h + 1, 0);
+				}
+				heights[h] += 1;
+				chips += h;
+			}
+		}
+		if by_chips.len() <= chips {
+			by_chips.resize(chips + 1, 0);
+		}
+		by_chips[chips] += 1;
+	}
+	let polynomial = |coefficients: &[u64]| {
+		let terms: Vec<_> = coefficients.iter().enumerate().filter(|&(_, &c)| c > 0).map(|(k, &c)| match (k, c) {
+			(0, c) => format!("{c}"),
+			(1, 1) => "q".to_owned(),
+			(1, c) => format!("{c}q"),
+			(k, 1) => format!("q^{k}"),
+			(k, c) => format!("{c}q^{k}"),
+		}).collect();
+		if terms.is_empty() {"0".to_owned()} else {terms.join(" + ")}
+	};
+	let min_chips = by_chips.iter().position(|&c| c > 0).unwrap_or(0);
+	writeln!(out, "Recurrent sandpiles: {count}")?;
+	writeln!(out, "By chips: {}", polynomial(&by_chips))?;
+	writeln!(out, "Level polynomial: {}", polynomial(&by_chips[min_chips..]))?;
+	let cells: u64 = heights.iter().sum();
+	for (h, &n) in heights.iter().enumerate() {
+		writeln!(out, "Height {h}: {n} ({})", n as f64 / cells as f64)?;
+	}
+	Ok(())
+}
+
+// The sandpiles of the group which the commands push without popping anything.
+fn generate(config: &Config, action: Action) -> Result<GridSandpile, Box<dyn Error>> {
+	let (grid_type, neighbourhood) = (config.grid_type.finite()?, config.neighbourhood.clone());
+	Ok(match (action, config.mask.clone()) {
+		(Action::Id, Some(mask)) => FiniteGridSandpile::neutral_masked(grid_type, neighbourhood, mask)?,
+		(Action::Id, None) => FiniteGridSandpile::neutral(grid_type, neighbourhood, config.dimensions),
+		(Action::Burn, Some(mask)) => FiniteGridSandpile::burn_masked(grid_type, neighbourhood, mask)?,
+		(Action::Burn, None) => FiniteGridSandpile::burn(grid_type, neighbourhood, config.dimensions),
+		(Action::RandomRecurrent(seed), Some(mask)) => FiniteGridSandpile::random_recurrent_masked(grid_type, neighbourhood, mask, seed)?,
+		(Action::RandomRecurrent(seed), None) => FiniteGridSandpile::random_recurrent(grid_type, neighbourhood, config.dimensions, seed)?,
+		(Action::ReadTree, mask) => {
+			let (x, y) = config.dimensions;
+			let tree = SpanningTree::new(grid_type, mask, read_tree(x, y)?);
+			FiniteGridSandpile::from_spanning_tree(neighbourhood, &tree)?
+		},
+		_ => unreachable!(),
+	})
+}
+
+// Runs the commands on the chips themselves, which never topple and may be negative: 'inverse' negates them,
+// and 'add', 'sub', and 'mul-K' work cell by cell. Then checks whether the two configurations left are equivalent.
+fn equiv(mut config: Config) -> Result<(), Box<dyn Error>> {
+	let (x, y) = config.dimensions;
+	let signed = |g: sandpile::Grid| -> Vec<Vec<BigInt>> {
+		g.into_iter().map(|row| row.into_iter().map(BigInt::from).collect()).collect()
+	};
+	let mut stack = Vec::new();
+	while let Some(action) = config.actions.pop() {
+		match action {
+			Action::Id | Action::Burn | Action::RandomRecurrent(_) | Action::ReadTree => stack.push(signed(generate(&config, action)?.into_grid())),
+			Action::Read => {
+				let mut g = String::new();
+				for _ in 0..y {
+					io::stdin().read_line(&mut g)?;
+				}
+				stack.push(signed(GridSandpile::grid_from_string(config.dimensions, config.mask.is_some(), g)?))
+			},
+			Action::ReadList => stack.push(signed(read_list(x, y)?)),
+			Action::All(n) | Action::AllDoubling(n) => stack.push(vec![vec![BigInt::from(n); x]; y]),
+			Action::Inverse => {
+				let a: Vec<Vec<BigInt>> = stack.pop().unwrap();
+				stack.push(a.into_iter().map(|row| row.into_iter().map(|c| -c).collect()).collect())
+			},
+			Action::Add | Action::Sub => {
+				let mut a = stack.pop().unwrap();
+				let b = stack.pop().unwrap();
+				for (row, brow) in a.iter_mut().zip(b) {
+					for (c, bc) in row.iter_mut().zip(brow) {
+						if action == Action::Add {*c += bc} else {*c -= bc}
+					}
+				}
+				stack.push(a)
+			},
+			Action::Mul(k) => {
+				let mut a = stack.pop().unwrap();
+				for c in a.iter_mut().flatten() {
+					*c *= k;
+				}
+				stack.push(a)
+			},
+			Action::Dup => {
+				let a = stack.last().unwrap().clone();
+				stack.push(a);
+			},
+		}
+	}
+	let (a, b) = (stack.pop().unwrap(), stack.pop().unwrap());
+	let equivalent = match &config.mask {
+		Some(mask) => FiniteGridSandpile::equivalent_masked(config.grid_type.finite()?, config.neighbourhood.clone(), mask, &a, &b)?,
+		None => FiniteGridSandpile::equivalent(config.grid_type.finite()?, config.neighbourhood.clone(), &a, &b)?,
+	};
+	println!("{equivalent}");
+	Ok(())
+}
+
+fn from_grid(config: &Config, grid: sandpile::Grid) -> Result<GridSandpile, sandpile::SandpileError> {
+	match (config.mask.clone(), config.background.clone()) {
+		(Some(mask), _) => GridSandpile::from_grid_masked(config.grid_type, config.neighbourhood.clone(), mask, grid),
+		(None, Some(background)) => GridSandpile::from_grid_on_background(config.grid_type, config.neighbourhood.clone(), background, grid),
+		(None, None) => GridSandpile::from_grid(config.grid_type, config.neighbourhood.clone(), grid),
+	}
+}
+
+fn run_lattice(mut config: Config, lattice_type: LatticeType, dimensions: Vec<usize>) -> Result<(), Box<dyn Error>> {
+	let mut stack = Vec::new();
+	let time = std::time::SystemTime::now();
+	while let Some(action) = config.actions.pop() {
+		match action {
+			Action::Id => stack.push(LatticeSandpile::neutral(lattice_type, dimensions.clone())?),
+			Action::Burn => stack.push(LatticeSandpile::burn(lattice_type, dimensions.clone())?),
+			Action::All(n) => stack.push(LatticeSandpile::from_cells(lattice_type, dimensions.clone(), vec![n; dimensions.iter().product()])?),
+			Action::Inverse => {
+				let a = stack.pop().unwrap().inverse()?;
+				stack.push(a)
+			},
+			Action::Add => {
+				let mut a = stack.pop().unwrap();
+				a.add(&stack.pop().unwrap())?;
+				stack.push(a)
+			},
+			Action::Sub => {
+				let mut a = stack.pop().unwrap();
+				a.add(&stack.pop().unwrap().inverse()?)?;
+				stack.push(a)
+			},
+			Action::Mul(k) => {
+				let a = stack.pop().unwrap();
+				stack.push(a.multiple(k))
+			},
+			Action::Dup => {
+				let a = stack.last().unwrap().clone();
+				stack.push(a);
+			},
+			Action::Read | Action::ReadList | Action::ReadTree | Action::RandomRecurrent(_) | Action::AllDoubling(_) => unreachable!(),
+		}
+	}
+	let a = stack.pop().unwrap();
+	if config.eq {
+		let a2 = stack.pop().unwrap();
+		println!("{}", a == a2);
+		return Ok(())
+	}
+	if config.topplings {
+		println!("Topplings: {}", a.last_topple());
+	}
+	if config.chips_count {
+		println!("Total chips count: {}", a.chips_count());
+	}
+	if config.order {
+		println!("Order: {}", a.order()?);
+	}
+	if config.time {
+		match time.elapsed() {
+			Ok(t) => println!("Total time taken: {}.{} s", t.as_secs(), t.subsec_millis()),
+			Err(e) => eprintln!("{e}"),
+		}
+	}
+	if config.out_ascii {
+		print!("{a}");
+	}
+	if let Some((mut filename, colors)) = config.out_png {
+		// One image per layer: out.png becomes out-0.png, out-1.png, etc.
+		let layers = a.layers();
+		while let Err(e) = layers.iter().enumerate().try_for_each(|(n, g)| png(g, &frame_name(&filename, n), &colors)) {
+			eprintln!("Can't write to file {}. {e}", frame_name(&filename, 0));
+			eprintln!("Please enter correct name for output file:");
+			filename = String::new();
+			io::stdin().read_line(&mut filename)?;
+			filename = filename.trim().to_string();
+		}
+	}
+	Ok(())
+}
+
 #[derive(Debug)]
 struct Config {
 	grid_type: GridType,
 	neighbourhood: Neighbourhood,
 	dimensions: (usize, usize),
+	mask: Option<Rc<Mask>>,
+	background: Option<Rc<sandpile::Grid>>,
+	lattice: Option<(LatticeType, Vec<usize>)>,	// For lattices of 3 and more dimensions.
 	out_ascii: bool,
 	out_png: Option<(String, Vec<[u8; 4]>)>,
+	burning: bool,
+	burning_png: Option<(String, Vec<[u8; 4]>)>,
+	tree: bool,
+	tree_png: Option<String>,
+	odometer: bool,
+	odometer_png: Option<(String, Vec<[u8; 4]>)>,
+	tropical: bool,
+	tropical_svg: Option<String>,
+	frames: Option<(String, Vec<[u8; 4]>)>,	// Images of the powers of two built by 'all-N --doubling'.
 	eq: bool,
+	equiv: bool,	// Like 'eq', but up to toppling, without stabilizing.
+	enumerate: Option<bool>,	// Whether to print every recurrent sandpile or only the summary.
 	order: bool,
+	order_by_addition: bool,
+	rho: bool,
+	group_structure: bool,
+	group_order: bool,
 	topplings: bool,
 	chips_count: bool,
 	time: bool,
@@ -122,78 +537,152 @@ enum Action {
 	Burn,
 	Read,
 	ReadList,
+	ReadTree,
+	RandomRecurrent(u64),
 	All(sandpile::Cell),
+	AllDoubling(sandpile::Cell),
 	Add,
+	Sub,
+	Mul(sandpile::Cell),
 	Dup,
 	Inverse,
 }
 
 impl Config {
 	fn new(args: &mut std::env::Args) -> Result<Config, String> {
+		let args = &mut args.peekable();
 		args.next();
 		let grid_type_err = Err("\
-Please specify grid type ('rectangle', 'torus', or 'infinite') as the 1st command line argument.
+Please specify grid type ('rectangle', 'torus', 'cylinder', 'mobius', 'klein', or 'infinite') as the 1st command line argument.
+Other boundaries are given side by side (top, right, bottom, left) as 'box:sink,reflect,sink,periodic'.
 To use Moore neighbourhood (8 neighbours), type 'rectangle.moore' etc.
+Triangular (6 neighbours) and hexagonal (3 neighbours) lattices are 'rectangle.tri' and 'rectangle.hex'.
+A custom neighbourhood is read from a file with lines 'dy dx' or 'dy dx weight' by 'rectangle.stencil:filename'.
+The infinite grid with background height 2 is 'infinite@2', and a tile of heights is read from a file by 'infinite@filename'.
 Example of a correct call (with cargo, use 'cargo run --release' instead of 'sandpile'):
 sandpile rectangle 60x50 ascii+png id out/id.png".to_owned());
 		let grid_type = match args.next() {
 			Some(s) => s,
 			None => return grid_type_err
 		};
-		let grid_type: Vec<_> = grid_type.split('.').collect();
+		let (grid_type, background) = match grid_type.split_once('@') {
+			Some((grid_type, background)) => (grid_type.to_owned(), Some(Rc::new(read_background(background)?))),
+			None => (grid_type, None)
+		};
+		let grid_type: Vec<_> = grid_type.splitn(2, '.').collect();
 		let (grid_type, neighbourhood) = (grid_type[0], match grid_type.len() {
 			1 => Neighbourhood::VonNeumann,
 			2 if grid_type[1] == "moore" => Neighbourhood::Moore,
+			2 if grid_type[1] == "tri" => Neighbourhood::Triangular,
+			2 if grid_type[1] == "hex" => Neighbourhood::Hexagonal,
+			2 if grid_type[1].starts_with("stencil:") => read_stencil(&grid_type[1]["stencil:".len()..])?,
 			_ => return grid_type_err
 		});
 		let grid_type = match grid_type {
 			"rectangle" | "rectangular" | "finite" => GridType::Finite(FiniteGridType::Rectangular),
 			"infinite" => GridType::Infinite(0, 0),
 			"torus" | "toroidal"  => GridType::Finite(FiniteGridType::Toroidal),
+			"cylinder" | "cylindrical" => GridType::Finite(FiniteGridType::Cylindrical),
+			"mobius" | "möbius" => GridType::Finite(FiniteGridType::Mobius),
+			"klein" => GridType::Finite(FiniteGridType::Klein),
+			s if s.starts_with("box:") => match read_sides(&s["box:".len()..]) {
+				Some(sides) => GridType::Finite(FiniteGridType::Box(sides)),
+				None => return grid_type_err
+			},
 			_ => return grid_type_err
 		};
-		let (x, y) = match || -> Option<_> {
-			let s = match args.next() {
+		let size = args.next();
+		let mask = match size.as_deref() {
+			Some(s) if s.starts_with("mask:") => Some(Rc::new(read_mask(&s["mask:".len()..])?)),
+			_ => None
+		};
+		let dimensions = match || -> Option<_> {
+			if let Some(mask) = &mask {
+				return Some(vec![mask[0].len(), mask.len()])
+			}
+			let s = match size {
 				Some(x) => x,
 				None => return None
 			};
-			if let Ok(x) = s.parse::<usize>() {
-				if x > 0 {
-					return Some((x, x))
-				}
+			if let Ok(x) = s.parse::<usize>() && x > 0 {
+				return Some(vec![x, x])
 			}
 			let sx: Vec<_> = s.split("x").collect();
-			if sx.len() != 2 {
+			if sx.len() < 2 {
 				return None
 			}
-			if let (Ok(x), Ok(y)) = (sx[0].parse::<usize>(), sx[1].parse::<usize>()) {
-				if x > 0 && y > 0 {
-					return Some((x, y))
+			let mut dimensions = Vec::new();
+			for s in sx {
+				match s.parse::<usize>() {
+					Ok(x) if x > 0 => dimensions.push(x),
+					_ => return None
 				}
 			}
-			None
+			Some(dimensions)
 		}() {
 			Some(dim) => dim,
-			None => return Err("Please specify grid size (as '100' or '200x100') as the 2nd command line argument.".to_owned())
+			None => return Err("Please specify grid size (as '100', '200x100', or '50x40x30' for a 3D lattice, or as 'mask:filename') as the 2nd command line argument.".to_owned())
 		};
+		let (x, y) = (dimensions[0], dimensions[1]);
+		let lattice = if dimensions.len() > 2 {
+			let lattice_type = match grid_type {
+				GridType::Finite(FiniteGridType::Rectangular) => LatticeType::Finite,
+				GridType::Finite(FiniteGridType::Toroidal) => LatticeType::Periodic,
+				GridType::Infinite(..) => LatticeType::Infinite,
+				_ => return Err("Lattices of 3 and more dimensions may only be 'rectangle', 'torus', or 'infinite'.".to_owned())
+			};
+			if neighbourhood != Neighbourhood::VonNeumann {
+				return Err("Lattices of 3 and more dimensions only support the nearest-neighbour rule.".to_owned())
+			}
+			Some((lattice_type, dimensions))
+		} else { None };
+		if let Ok(t) = grid_type.finite() && neighbourhood.is_symmetric() && !t.is_directed()
+		 && !FiniteGridSandpile::is_symmetric(t, &neighbourhood, (x, y)) {
+			return Err("\
+The lattice does not fit this surface: some cell gives more chips to a neighbour than it gets from it.
+E.g., triangular and hexagonal lattices on a torus need an even number of rows.".to_owned())
+		}
 		let mut group = false;
 		let mut out_ascii = false;
 		let mut out_png = false;
+		let mut burning = false;
+		let mut burning_png = false;
+		let mut tree = false;
+		let mut tree_png = false;
+		let mut odometer = false;
+		let mut odometer_png = false;
+		let mut tropical = false;
+		let mut tropical_svg = false;
 		let mut time = false;
 		let mut topplings = false;
 		let mut chips_count = false;
 		let mut order = false;
+		let mut order_by_addition = false;
+		let mut rho = false;
+		let mut group_structure = false;
+		let mut group_order = false;
 		let mut eq = false;
+		let mut equiv = false;
+		let mut enumerate = None;
+		let mut frames = None;
 		let mut actions = Vec::new();
 		let mut actions_expected = 1;
 		if let Some(s) = args.next() {
 			if s == "eq" {
 				eq = true;
 				actions_expected = 2;
+			} else if s == "equiv" {
+				equiv = true;
+				actions_expected = 2;
+				group = true;
 			} else if s == "recurrent" {
 				eq = true;
 				actions = vec![Action::Add, Action::Burn, Action::Dup];
 				group = true;
+			} else if s == "enumerate" || s == "enumerate-summary" {
+				enumerate = Some(s == "enumerate");
+				actions_expected = 0;
+				group = true;
 			} else {
 				for out in s.split("+") {
 					match out {
@@ -203,14 +692,32 @@ sandpile rectangle 60x50 ascii+png id out/id.png".to_owned());
 						"topplings" => topplings = true,
 						"chips" | "grains" => chips_count = true,
 						"order" => {group = true; order = true},
+						"order-by-addition" => {group = true; order_by_addition = true},
+						"rho" => {group = true; rho = true},
+						"group" => {group = true; group_structure = true},
+						"group-order" => {group = true; group_order = true},
+						"burning" => {group = true; burning = true},
+						"burning-png" => {group = true; burning_png = true},
+						"tree" => {group = true; tree = true},
+						"tree-png" => {group = true; tree_png = true},
+						"odometer" => odometer = true,
+						"odometer-png" => odometer_png = true,
+						"tropical" => tropical = true,
+						"tropical-svg" => tropical_svg = true,
 						_ => return Err(format!("\
 Expected output format
-either '+'-separated 'ascii', 'png', 'time', 'topplings', 'chips', and/or 'order'
-or sole 'eq' or 'recurrent'.
+either '+'-separated 'ascii', 'png', 'time', 'topplings', 'chips', 'order', 'order-by-addition', 'rho', 'group', 'group-order',
+'burning', 'burning-png', 'tree', 'tree-png', 'odometer', 'odometer-png', 'tropical', and/or 'tropical-svg'
+or sole 'eq', 'equiv', 'recurrent', 'enumerate', or 'enumerate-summary'.
 Got: {s}"))
 					}
 				}
 			}
+			// 'group' and 'group-order' alone, with 'time' or not, need no commands.
+			if (group_structure || group_order) && !(out_ascii || out_png || topplings || chips_count || order || order_by_addition || rho
+			 || burning || burning_png || tree || tree_png || odometer || odometer_png || tropical || tropical_svg) {
+				actions_expected = 0;
+			}
 		} else {
 			return Err("Please specify desired output (e.g., 'ascii') as the 3rd command line argument.".to_owned())
 		};
@@ -218,7 +725,7 @@ Got: {s}"))
 			let arg = match args.next() {
 				Some(s) => s,
 				None => return Err(if actions.is_empty() {
-					"Please specify command: 'id', 'read', 'read_list', 'all-N', 'burn', 'inverse', 'dup', or 'add'."
+					"Please specify command: 'id', 'read', 'read_list', 'read_tree', 'random-recurrent', 'all-N', 'burn', 'inverse', 'dup', 'add', 'sub', or 'mul-K'."
 				} else {
 					"Command list terminated unexpectedly."
 				}.to_owned())
@@ -228,39 +735,114 @@ Got: {s}"))
 				"burn" => {group = true; (Action::Burn, 0)},
 				"read" => (Action::Read, 0),
 				"read_list" => (Action::ReadList, 0),
+				"read_tree" => {group = true; (Action::ReadTree, 0)},
+				"random-recurrent" => {
+					group = true;
+					let seed = if args.next_if_eq("--seed").is_some() {
+						match args.next().map(|s| s.parse()) {
+							Some(Ok(seed)) => seed,
+							_ => return Err("In command 'random-recurrent --seed N', N must be a 64-bit number.".to_owned()),
+						}
+					} else {
+						std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |t| t.as_nanos() as u64)
+					};
+					(Action::RandomRecurrent(seed), 0)
+				},
 				s if s.starts_with("all-") => match s[4..].parse::<sandpile::Cell>() {
+					Ok(n) if args.next_if_eq("--doubling").is_some() => {
+						if args.next_if_eq("--frames").is_some() {
+							if frames.is_some() {
+								return Err("Only one 'all-N --doubling --frames FILE' is possible.".to_owned())
+							}
+							frames = match args.next() {
+								Some(filename) => Some(filename),
+								None => return Err("Please specify name for the png files after 'all-N --doubling --frames'.".to_owned()),
+							};
+						}
+						(Action::AllDoubling(n), 0)
+					},
 					Ok(n) => (Action::All(n), 0),
 					Err(_e) => return Err("In command 'all-N', N must be a 128-bit number.".to_owned()),
 				},
 				"inverse" => {group = true; (Action::Inverse, 1)},
 				"add" => (Action::Add, 2),
+				"sub" => {group = true; (Action::Sub, 2)},
+				s if s.starts_with("mul-") => match s[4..].parse::<sandpile::Cell>() {
+					Ok(k) => (Action::Mul(k), 1),
+					Err(_e) => return Err("In command 'mul-K', K must be a 128-bit number.".to_owned()),
+				},
 				"dup" => (Action::Dup, 0),
 				s => return Err(format!("Unknown command: {s}"))
 			};
 			actions.push(action);
 			actions_expected += incr - 1;
 		}
-		if *actions.last().unwrap() == Action::Dup {
+		if actions.last() == Some(&Action::Dup) {
 			return Err("'dup' duplicates the top sandpile on the stack, so at the point it occurs at least 2 commands should be expected, and at least 1 more command should follow.".to_owned());
 		}
-		let out_png = if out_png {
-			let colors = get_colors()?;
+		if [out_png, burning_png, tree_png, odometer_png, tropical_svg].iter().filter(|&&b| b).count() > 1 {
+			return Err("Outputs 'png', 'burning-png', 'tree-png', 'odometer-png', and 'tropical-svg' all need the final command line argument, so only one of them can be specified.".to_owned())
+		}
+		let png_file = if out_png || burning_png || tree_png || odometer_png || tropical_svg {
 			match args.next() {
-				Some(filename) => Some((filename, colors)),
-				None => return Err("Please specify name for output png file as the final command line argument.".to_owned())
+				Some(filename) => Some(filename),
+				None => return Err(format!("Please specify name for output {} file as the final command line argument.", if tropical_svg {"svg"} else {"png"}))
 			}
 		} else { None };
+		let (png_file, tropical_svg) = if tropical_svg {(None, png_file)} else {(png_file, None)};
+		let colors = if out_png || burning_png || odometer_png || frames.is_some() {Some(get_colors()?)} else { None };
+		let frames = frames.map(|filename| (filename, colors.clone().unwrap()));
+		let (out_png, burning_png, tree_png, odometer_png) = match (png_file, colors) {
+			(Some(filename), Some(colors)) if out_png => (Some((filename, colors)), None, None, None),
+			(Some(filename), Some(colors)) if burning_png => (None, Some((filename, colors)), None, None),
+			(Some(filename), Some(colors)) => (None, None, None, Some((filename, colors))),
+			(png_file, _) => (None, None, png_file, None),
+		};
+		if lattice.is_some() && (group_structure || group_order || burning || burning_png.is_some() || tree || tree_png.is_some() || odometer || odometer_png.is_some() || equiv) {
+			return Err("Outputs 'group', 'group-order', 'burning', 'burning-png', 'tree', 'tree-png', 'odometer', 'odometer-png', and 'equiv' are unavailable for lattices of 3 and more dimensions.".to_owned())
+		}
+		if (tropical || tropical_svg.is_some()) && (lattice.is_some() || mask.is_some() || grid_type.finite().ok().and_then(|t| t.sides()) != Some([Boundary::Sink; 4])
+			|| !matches!(neighbourhood, Neighbourhood::VonNeumann | Neighbourhood::Moore)) {
+			return Err("Outputs 'tropical' and 'tropical-svg' are only available for rectangles with the sink all around, without a mask, and with the von Neumann or Moore neighbourhood.".to_owned())
+		}
+		if lattice.is_some() && actions.iter().any(|&a| matches!(a, Action::Read | Action::ReadList | Action::ReadTree | Action::RandomRecurrent(_) | Action::AllDoubling(_))) {
+			return Err("Commands 'read', 'read_list', 'read_tree', 'random-recurrent', and 'all-N --doubling' are unavailable for lattices of 3 and more dimensions.".to_owned())
+		}
+		if background.is_some() && (grid_type.finite().is_ok() || lattice.is_some()) {
+			return Err("Only the infinite grid may have a background.".to_owned())
+		}
+		if mask.is_some() && grid_type.finite().is_err() {
+			return Err("The infinite grid can't be masked.".to_owned())
+		}
 		if grid_type.finite().is_err() && group {
-			return Err("For the infinite grid, outputs 'order' and 'recurrent' and commands 'id', 'burn', and 'inverse' are impossible.".to_owned())
+			return Err("For the infinite grid, outputs 'order', 'order-by-addition', 'rho', 'group', 'group-order', 'burning', 'burning-png', 'tree', 'tree-png', 'recurrent', and 'equiv' and commands 'id', 'burn', 'inverse', 'read_tree', 'random-recurrent', and 'sub' are impossible.".to_owned())
 		}
 		Ok(Config {
 			grid_type,
 			neighbourhood,
 			dimensions: (x, y),
+			mask,
+			background,
+			lattice,
 			out_ascii,
 			out_png,
+			burning,
+			burning_png,
+			tree,
+			tree_png,
+			odometer,
+			odometer_png,
+			tropical,
+			tropical_svg,
+			frames,
 			eq,
+			equiv,
+			enumerate,
 			order,
+			order_by_addition,
+			rho,
+			group_structure,
+			group_order,
 			topplings,
 			chips_count,
 			time,
@@ -278,7 +860,7 @@ fn read_list(x: usize, y: usize) -> Result<sandpile::Grid, Box<dyn Error>> {
 	let mut grid = vec![vec![0; x]; y];
 	for s in g[..g.len()-1].split_terminator(',') {
 		let ss: Vec<_> = s.split_whitespace().collect();
-		if ss.len() == 0 {
+		if ss.is_empty() {
 			continue
 		}
 		if ss.len() != 2 {
@@ -293,6 +875,110 @@ fn read_list(x: usize, y: usize) -> Result<sandpile::Grid, Box<dyn Error>> {
 	Ok(grid)
 }
 
+// Reads the edges of a spanning tree from stdin till the end, one per line as in the 'tree' output: 'x y: dy dx' or 'x y: dy dx k'.
+fn read_tree(x: usize, y: usize) -> Result<Tree, Box<dyn Error>> {
+	let mut tree = vec![vec![None; x]; y];
+	for line in io::stdin().lines() {
+		let line = line?;
+		let line = line.split('#').next().unwrap();
+		if line.trim().is_empty() {
+			continue
+		}
+		let err = || format!("Expected 'x y: dy dx' or 'x y: dy dx k', got: {line}");
+		let (cell, offset) = line.split_once(':').ok_or_else(err)?;
+		let (xc, yc) = match cell.split_whitespace().collect::<Vec<_>>()[..] {
+			[xc, yc] => (xc.parse::<usize>()?, yc.parse::<usize>()?),
+			_ => return Err(err().into())
+		};
+		let (dy, dx, k) = match offset.split_whitespace().collect::<Vec<_>>()[..] {
+			[dy, dx] => (dy.parse()?, dx.parse()?, 0),
+			[dy, dx, k] => (dy.parse()?, dx.parse()?, k.parse()?),
+			_ => return Err(err().into())
+		};
+		if xc >= x || yc >= y {
+			return Err(format!("Coordinates ({xc}, {yc}) out of bounds (0..{x}, 0..{y})").into())
+		}
+		tree[yc][xc] = Some((dy, dx, k));
+	}
+	Ok(tree)
+}
+
+fn read_stencil(filename: &str) -> Result<Neighbourhood, String> {
+	let stencil_file = match fs::read_to_string(filename) {
+		Ok(s) => s,
+		Err(e) => return Err(format!("Can't read stencil file {filename}. {e}"))
+	};
+	let mut stencil = Vec::new();
+	for line in stencil_file.lines() {
+		let line = line.split('#').next().unwrap();
+		let ss: Vec<_> = line.split_whitespace().collect();
+		if ss.is_empty() {
+			continue
+		}
+		let err = || format!("Expected 'dy dx' or 'dy dx weight' in the stencil file, got: {line}");
+		let (dy, dx, w) = match ss[..] {
+			[dy, dx] => (dy, dx, "1"),
+			[dy, dx, w] => (dy, dx, w),
+			_ => return Err(err())
+		};
+		match (dy.parse(), dx.parse(), w.parse()) {
+			(Ok(dy), Ok(dx), Ok(w)) => stencil.push((dy, dx, w)),
+			_ => return Err(err())
+		}
+	}
+	Neighbourhood::custom(stencil).map_err(|e| e.to_string())
+}
+
+// Either a single height or a file with a tile of heights, one row per line.
+fn read_background(s: &str) -> Result<sandpile::Grid, String> {
+	if let Ok(h) = s.parse() {
+		return Ok(vec![vec![h]])
+	}
+	let tile_file = match fs::read_to_string(s) {
+		Ok(s) => s,
+		Err(e) => return Err(format!("Can't read background file {s}. {e}"))
+	};
+	let mut tile = Vec::new();
+	for line in tile_file.lines() {
+		let row: Result<Vec<_>, _> = line.split_whitespace().map(|h| h.parse()).collect();
+		match row {
+			Ok(row) if row.is_empty() => continue,
+			Ok(row) => tile.push(row),
+			Err(_) => return Err(format!("Expected heights separated by spaces in the background file, got: {line}"))
+		}
+	}
+	Ok(tile)
+}
+
+// A png mask is black where the cells are absent, and a text one has 'x' there.
+fn read_mask(filename: &str) -> Result<Mask, String> {
+	if filename.to_lowercase().ends_with(".png") {
+		return mask_from_png(filename).map_err(|e| format!("Can't read mask image {filename}. {e}"))
+	}
+	let mask_file = match fs::read_to_string(filename) {
+		Ok(s) => s,
+		Err(e) => return Err(format!("Can't read mask file {filename}. {e}"))
+	};
+	mask_from_string(&mask_file).map_err(|e| e.to_string())
+}
+
+fn read_sides(s: &str) -> Option<[Boundary; 4]> {
+	let mut sides = [Boundary::Sink; 4];
+	let ss: Vec<_> = s.split(',').collect();
+	if ss.len() != 4 {
+		return None
+	}
+	for (side, s) in sides.iter_mut().zip(ss) {
+		*side = match s {
+			"sink" => Boundary::Sink,
+			"periodic" => Boundary::Periodic,
+			"reflect" | "reflecting" => Boundary::Reflecting,
+			_ => return None
+		}
+	}
+	Some(sides)
+}
+
 fn hex_to_int(s: &str) -> Option<[u8; 4]> {
 	let ch: Vec<_> = s.chars().collect();
 	if ch.len() < 6 {
@@ -0,0 +1,92 @@
+use super::*;
+
+use std::io::BufReader;
+
+// Cells of a finite grid which belong to the domain. Absent cells act as sink.
+pub type Mask = Vec<Vec<bool>>;
+
+// Character for absent cells in the ascii representation of sandpiles and masks.
+pub const MASKED: char = 'x';
+
+// Reads a mask from text where `MASKED` stands for an absent cell and any other character for a present one.
+// Shorter lines are padded with absent cells.
+pub fn mask_from_string(s: &str) -> Result<Mask, SandpileError> {
+	let mut mask: Mask = s.lines().map(|line| line.chars().map(|ch| ch != MASKED).collect()).collect();
+	let x = mask.iter().map(|row| row.len()).max().unwrap_or(0);
+	if x == 0 {
+		return Err(SandpileError::EmptyGrid);
+	}
+	for row in &mut mask {
+		row.resize(x, false);
+	}
+	Ok(mask)
+}
+
+// Reads a mask from a png image where dark pixels stand for absent cells and light ones for present cells.
+pub fn mask_from_png(fname: &str) -> Result<Mask, Box<dyn Error>> {
+	let mut decoder = ::png::Decoder::new(BufReader::new(File::open(fname)?));
+	decoder.set_transformations(::png::Transformations::normalize_to_color8());
+	let mut reader = decoder.read_info()?;
+	let mut buf = vec![0; reader.output_buffer_size().ok_or("The png image is too large.")?];
+	let info = reader.next_frame(&mut buf)?;
+	let samples = info.color_type.samples();
+	let luma = |p: &[u8]| -> u32 {
+		if samples < 3 {
+			p[0] as u32
+		} else {
+			(299 * p[0] as u32 + 587 * p[1] as u32 + 114 * p[2] as u32) / 1000
+		}
+	};
+	let mask = buf[..info.buffer_size()].chunks(info.line_size)
+		.map(|line| line.chunks(samples).take(info.width as usize).map(|p| luma(p) >= 128).collect())
+		.collect();
+	Ok(mask)
+}
+
+pub(super) fn verify_mask(mask: &Mask, (x, y): (usize, usize)) -> Result<(), SandpileError> {
+	if mask.len() != y || mask.iter().any(|row| row.len() != x) {
+		return Err(SandpileError::UnequalDimensions(x, y, mask.first().map_or(0, |row| row.len()), mask.len()));
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn masked_domains() {
+		// The L-shaped domain is the 4×4 square without its top-right 2×2 quadrant.
+		let mask = Rc::new(mask_from_string("..xx\n..xx\n....\n....").unwrap());
+		let e = FiniteGridSandpile::neutral_masked(FiniteGridType::Rectangular, Neighbourhood::VonNeumann, mask.clone()).unwrap();
+		let mut e2 = e.clone();
+		e2.add(&e).unwrap();
+		assert_eq!(e, e2);
+		let mut b = FiniteGridSandpile::burn_masked(FiniteGridType::Rectangular, Neighbourhood::VonNeumann, mask.clone()).unwrap();
+		assert_eq!(b.clone().into_grid()[1], [1, 1, 0, 0]);
+		b.add(&e).unwrap();
+		assert_eq!(b, e);
+		let a = GridSandpile::from_grid_masked(GridType::Finite(FiniteGridType::Rectangular), Neighbourhood::VonNeumann, mask.clone(), vec![vec![3; 4]; 4]).unwrap();
+		let mut i = FiniteGridSandpile::try_from(&a).unwrap().inverse();
+		i.add(&a).unwrap();
+		assert_eq!(i, e);
+		assert_eq!(format!("{e}").lines().next().unwrap().chars().filter(|&ch| ch == MASKED).count(), 2);
+		// The domain is the graph of the sandpile.
+		let graph = Rc::new(FiniteGridSandpile::graph(FiniteGridType::Rectangular, Neighbourhood::VonNeumann, (4, 4)));
+		let mut adjacency = vec![Vec::new(); 13];
+		let cells: Vec<_> = (0..16).filter(|&v| mask[v/4][v%4]).collect();
+		for (n, &v) in cells.iter().enumerate() {
+			for &(u, m) in graph.edges(v) {
+				adjacency[n].push((cells.iter().position(|&w| w == u).unwrap_or(12), m));
+			}
+			let to_sink = 4 - adjacency[n].iter().filter(|&&(u, _)| u != 12).count() as Cell;
+			adjacency[12].push((n, to_sink));
+		}
+		let l = GraphSandpile::neutral(Rc::new(Graph::undirected(adjacency, 12).unwrap()));
+		let g = e.into_grid();
+		assert_eq!(cells.iter().map(|&v| g[v/4][v%4]).collect::<Vec<_>>(), l.config()[..12]);
+		// Dimensions of the mask and the grid must agree, and the infinite grid can't be masked.
+		assert!(GridSandpile::from_grid_masked(GridType::Finite(FiniteGridType::Toroidal), Neighbourhood::VonNeumann, mask.clone(), vec![vec![0; 3]; 4]).is_err());
+		assert!(GridSandpile::from_grid_masked(GridType::Infinite(0, 0), Neighbourhood::VonNeumann, mask, vec![vec![0; 4]; 4]).is_err());
+	}
+}
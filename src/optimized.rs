@@ -8,6 +8,7 @@ impl GridSandpile {
 		let mut excessive = vec![(0, 0)];
 		let mut ex2 = vec![];
 		let mut count = 0;
+		let mut odometer = vec![vec![0]];
 		while !excessive.is_empty() {
 			for &(i, j) in &excessive {
 				let d = self.grid[i][j] / self.neighbourhood.neighbours();
@@ -15,12 +16,15 @@ impl GridSandpile {
 					continue;
 				}
 				if i + 1 == self.grid.len() {
-					for row in self.grid.iter_mut() {
-						row.push(0);
+					for g in [&mut self.grid, &mut odometer] {
+						for row in g.iter_mut() {
+							row.push(0);
+						}
+						g.push(vec![0; g[0].len()]);
 					}
-					self.grid.push(vec![0; self.grid[0].len()]);
 				}
 				self.grid[i][j] %= self.neighbourhood.neighbours();
+				odometer[i][j] += d;
 				count += match (i, j) {
 					(0, 0) => 1,
 					(_, 0) => 4,
@@ -56,10 +60,8 @@ impl GridSandpile {
 				} else { &[] };
 				for &(ti, tj) in topple_to.iter().chain(topple_to_2.iter()) {
 					self.grid[ti][tj] += if (ti, tj) == (0, 0) {4*d} else {d};
-					if let Some(p) = ex2.last() {
-						if *p == (ti, tj) {
-							continue
-						}
+					if ex2.last() == Some(&(ti, tj)) {
+						continue
 					}
 					if self.grid[ti][tj] >= self.neighbourhood.neighbours() {
 						ex2.push((ti, tj));
@@ -71,23 +73,232 @@ impl GridSandpile {
 		}
 		self.last_topple = count;
 		self.grid_type = GridType::Infinite(self.grid.len()-1, self.grid.len()-1);
-		for i in 1..self.grid.len() {
-			for j in 0..i {
-				self.grid[j][i] = self.grid[i][j];
+		unfold_octant(&mut self.grid);
+		unfold_octant(&mut odometer);
+		self.odometer = Some(odometer);
+	}
+}
+
+// The cells (i, j) with j <= i of a grid centred at (0, 0) are reflected to the whole grid centred at its middle cell.
+#[allow(clippy::needless_range_loop)]
+fn unfold_octant(grid: &mut Grid) {
+	for i in 1..grid.len() {
+		for j in 0..i {
+			grid[j][i] = grid[i][j];
+		}
+	}
+	for row in grid.iter_mut() {
+		let mut mirrow: Vec<_> = row.iter().skip(1).rev().copied().collect();
+		mirrow.append(row);
+		*row = mirrow;
+	}
+	let mut mirrid: Vec<_> = grid.iter().skip(1).rev().cloned().collect();
+	mirrid.append(grid);
+	*grid = mirrid;
+}
+
+// Symmetries of a configuration on the infinite grid about the centre of its support.
+// Coordinates (y, x) relative to the centre are doubled, so that they are integer when the centre is between cells.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Symmetry {
+	D4,     	// All the symmetries of the square. Fundamental domain 0 <= x <= y.
+	D2,     	// Reflections in both axes. Fundamental domain 0 <= x, 0 <= y.
+	MirrorY,	// Reflection y -> -y. Fundamental domain 0 <= y.
+	MirrorX,	// Reflection x -> -x. Fundamental domain 0 <= x.
+}
+
+impl Symmetry {
+	fn fold(self, (y, x): (isize, isize)) -> (isize, isize) {
+		match self {
+			Symmetry::D4 => if x.abs() > y.abs() {(x.abs(), y.abs())} else {(y.abs(), x.abs())},
+			Symmetry::D2 => (y.abs(), x.abs()),
+			Symmetry::MirrorY => (y.abs(), x),
+			Symmetry::MirrorX => (y, x.abs()),
+		}
+	}
+
+	// Number of the cells symmetric to a cell of the fundamental domain, including itself.
+	fn orbit(self, (y, x): (isize, isize)) -> Cell {
+		let (ny, nx) = ((y != 0) as Cell + 1, (x != 0) as Cell + 1);
+		match self {
+			Symmetry::D4 => ny * nx * ((x != y) as Cell + 1),
+			Symmetry::D2 => ny * nx,
+			Symmetry::MirrorY => ny,
+			Symmetry::MirrorX => nx,
+		}
+	}
+}
+
+impl GridSandpile {
+	// For a configuration with a symmetry, only the fundamental domain topples, and the grid is restored afterwards
+	// with the same bounds as the ordinary toppling would give. Returns None if there is no symmetry to use.
+	#[allow(clippy::needless_range_loop)]
+	pub(super) fn symmetric_infinite_optimized(&mut self) -> Option<u64> {
+		let GridType::Infinite(oy, ox) = self.grid_type else { return None };
+		let threshold = self.neighbourhood.neighbours();
+		if !self.grid.iter().flatten().any(|&el| el >= threshold) {
+			return None
+		}
+		// Support of the configuration.
+		let rows: Vec<_> = (0..self.grid.len()).filter(|&i| self.grid[i].iter().any(|&el| el > 0)).collect();
+		let cols: Vec<_> = (0..self.grid[0].len()).filter(|&j| self.grid.iter().any(|row| row[j] > 0)).collect();
+		let (bi, bj) = (rows[0], cols[0]);
+		let (h, w) = (rows[rows.len()-1] + 1 - bi, cols[cols.len()-1] + 1 - bj);
+		let g = |i: usize, j: usize| self.grid[bi + i][bj + j];
+		let mirror_y = (0..h).all(|i| (0..w).all(|j| g(i, j) == g(h-1-i, j)));
+		let mirror_x = (0..h).all(|i| (0..w).all(|j| g(i, j) == g(i, w-1-j)));
+		let transpose = h == w && (0..h).all(|i| (0..w).all(|j| g(i, j) == g(j, i)));
+		let symmetry = match (mirror_y, mirror_x, transpose) {
+			(true, true, true) => Symmetry::D4,
+			(true, true, false) => Symmetry::D2,
+			(true, false, _) => Symmetry::MirrorY,
+			(false, true, _) => Symmetry::MirrorX,
+			(false, false, _) => return None,
+		};
+		let (h, w) = (h as isize, w as isize);
+		// The domain is stored as a rectangle whose cell (a, b) has coordinates (lo_y + 2a, lo_x + 2b).
+		let (folded_y, folded_x) = (symmetry != Symmetry::MirrorX, symmetry != Symmetry::MirrorY);
+		let (mut lo_y, mut lo_x) = (if folded_y {(h-1) % 2} else {1-h}, if folded_x {(w-1) % 2} else {1-w});
+		let (mut dh, mut dw) = (((h-1 - lo_y) / 2 + 1) as usize, ((w-1 - lo_x) / 2 + 1) as usize);
+		if symmetry == Symmetry::D4 {
+			(dh, dw) = (dh.max(dw), dh.max(dw));
+		}
+		let mut domain = vec![vec![0; dw]; dh];
+		let mut excessive = Vec::new();
+		for a in 0..dh {
+			for b in 0..dw {
+				let (y, x) = (lo_y + 2*a as isize, lo_x + 2*b as isize);
+				if symmetry.fold((y, x)) != (y, x) || y.abs() >= h || x.abs() >= w {
+					continue;
+				}
+				domain[a][b] = g(((y + h-1) / 2) as usize, ((x + w-1) / 2) as usize);
+				if domain[a][b] >= threshold {
+					excessive.push((a, b));
+				}
+			}
+		}
+		let mut odometer = vec![vec![0; dw]; dh];
+		let offsets = self.neighbourhood.offsets((0, 0)).to_vec();
+		let r = self.neighbourhood.radius();
+		let mut ex2 = Vec::new();
+		let mut targets: Vec<((usize, usize), Cell)> = Vec::new();
+		let mut count = 0;
+		let mut toppled: Option<(isize, isize, isize, isize)> = None;	// Bounds of the toppled cells of the domain: y, x from and to.
+		while !excessive.is_empty() {
+			// The domain grows so that the targets of every toppling are in it.
+			let (mut top, mut left, mut bottom, mut right) = (0, 0, 0, 0);
+			for &(a, b) in &excessive {
+				if !folded_y && a < r {
+					top = top.max(r - a);
+				}
+				if !folded_x && b < r {
+					left = left.max(r - b);
+				}
+				bottom = bottom.max((a + r + 1).saturating_sub(dh));
+				right = right.max((b + r + 1).saturating_sub(dw));
+			}
+			// At least twice at a time, since the unused cells are cut off in the end.
+			let at_least = |k: usize, d: usize| if k > 0 {k.max(d)} else {0};
+			(top, left, bottom, right) = (at_least(top, dh), at_least(left, dw), at_least(bottom, dh), at_least(right, dw));
+			if symmetry == Symmetry::D4 {
+				(bottom, right) = (bottom.max(right), bottom.max(right));
+			}
+			if top + left + bottom + right > 0 {
+				for g in [&mut domain, &mut odometer] {
+					for row in g.iter_mut() {
+						row.splice(0..0, std::iter::repeat_n(0, left));
+						row.resize(left + dw + right, 0);
+					}
+					g.splice(0..0, std::iter::repeat_n(vec![0; left + dw + right], top));
+					g.resize(top + dh + bottom, vec![0; left + dw + right]);
+				}
+				for (a, b) in excessive.iter_mut() {
+					(*a, *b) = (*a + top, *b + left);
+				}
+				(lo_y, lo_x) = (lo_y - 2*top as isize, lo_x - 2*left as isize);
+				(dh, dw) = (top + dh + bottom, left + dw + right);
+			}
+			for &(a, b) in &excessive {
+				let d = domain[a][b] / threshold;
+				if d == 0 {
+					continue;
+				}
+				domain[a][b] %= threshold;
+				odometer[a][b] += d;
+				let (y, x) = (lo_y + 2*a as isize, lo_x + 2*b as isize);
+				let orbit = symmetry.orbit((y, x));
+				count += (d * orbit) as u64;
+				toppled = Some(match toppled {
+					Some((y0, x0, y1, x1)) => (y0.min(y), x0.min(x), y1.max(y), x1.max(x)),
+					None => (y, x, y, x),
+				});
+				// Far from the axes of symmetry, the neighbours are in the domain and have orbits of the same size.
+				let r2 = 2 * r as isize;
+				if (!folded_y || y > r2) && (!folded_x || x > r2) && (symmetry != Symmetry::D4 || y - x > 2*r2) {
+					for &(di, dj, wt) in &offsets {
+						let (ta, tb) = ((a as isize + di) as usize, (b as isize + dj) as usize);
+						domain[ta][tb] += d * wt;
+						if domain[ta][tb] >= threshold {
+							ex2.push((ta, tb));
+						}
+					}
+					continue;
+				}
+				// Chips from all the cells symmetric to (a, b), counted once for every cell of the orbit of the target.
+				targets.clear();
+				for &(di, dj, wt) in &offsets {
+					let (ty, tx) = symmetry.fold((y + 2*di, x + 2*dj));
+					let t = (((ty - lo_y) / 2) as usize, ((tx - lo_x) / 2) as usize);
+					match targets.iter_mut().find(|(u, _)| *u == t) {
+						Some((_, n)) => *n += wt,
+						None => targets.push((t, wt)),
+					}
+				}
+				for &((ta, tb), n) in &targets {
+					let t_orbit = symmetry.orbit((lo_y + 2*ta as isize, lo_x + 2*tb as isize));
+					domain[ta][tb] += d * n * orbit / t_orbit;
+					if domain[ta][tb] >= threshold {
+						ex2.push((ta, tb));
+					}
+				}
 			}
+			(excessive, ex2) = (ex2, excessive);
+			ex2.clear();
 		}
-		for row in &mut self.grid {
-			let mut mirrow: Vec<_> = row.iter().skip(1).rev().map(|x| *x).collect();
-			mirrow.append(row);
-			*row = mirrow;
+		// The bounds of the grid: the former ones and the neighbourhoods of all the toppled cells.
+		let (mut y0, mut x0) = (-2*bi as isize - (h-1), -2*bj as isize - (w-1));
+		let (mut y1, mut x1) = (y0 + 2*(self.grid.len() as isize - 1), x0 + 2*(self.grid[0].len() as isize - 1));
+		let (fy0, fx0) = (y0, x0);
+		if let Some((ty0, tx0, ty1, tx1)) = toppled {
+			let (ty0, tx0, ty1, tx1) = match symmetry {
+				Symmetry::D4 => {
+					let m = ty1.max(tx1);
+					(-m, -m, m, m)
+				},
+				Symmetry::D2 => (-ty1, -tx1, ty1, tx1),
+				Symmetry::MirrorY => (-ty1, tx0, ty1, tx1),
+				Symmetry::MirrorX => (ty0, -tx1, ty1, tx1),
+			};
+			let r = r as isize;
+			(y0, x0, y1, x1) = (y0.min(ty0 - 2*r), x0.min(tx0 - 2*r), y1.max(ty1 + 2*r), x1.max(tx1 + 2*r));
 		}
-		let mut mirrid: Vec<_> = self.grid.iter().skip(1).rev().map(|x| x.clone()).collect();
-		mirrid.append(&mut self.grid);
-		self.grid = mirrid;
+		let unfold = |domain: &Grid| -> Grid {
+			(0..=(y1 - y0) / 2).map(|i| (0..=(x1 - x0) / 2).map(|j| {
+				let (y, x) = symmetry.fold((y0 + 2*i, x0 + 2*j));
+				let (a, b) = ((y - lo_y) / 2, (x - lo_x) / 2);
+				if a >= 0 && b >= 0 && (a as usize) < dh && (b as usize) < dw {domain[a as usize][b as usize]} else {0}
+			}).collect()).collect()
+		};
+		self.grid = unfold(&domain);
+		self.odometer = Some(unfold(&odometer));
+		self.grid_type = GridType::Infinite(oy + ((fy0 - y0) / 2) as usize, ox + ((fx0 - x0) / 2) as usize);
+		self.last_topple = count;
+		Some(count)
 	}
 }
 
 impl<'a> FiniteGridSandpile<'a> {
+	// The odometer and the topplings are those of the second stabilization, as for the unoptimized identity.
 	pub(super) fn neutral_plus_rect_vn_es_optimized(x: usize, plus: Cell) -> GridSandpile { // es = even square
 		let t = 6;
 		let mut symmetric_grid: Vec<_> = (0..x).map(|i| vec![t; i+1]).collect();
@@ -97,23 +308,10 @@ impl<'a> FiniteGridSandpile<'a> {
 				*el = t + plus - *el;
 			}
 		}
-		topple_rect_vn_es_optimized(&mut symmetric_grid);
-		for i in 0..x {
-			for j in i+1..x {
-				let sc = symmetric_grid[j][i];
-				symmetric_grid[i].push(sc);
-			}
-		}
-		let mut grid = Vec::new();
-		while let Some(mut s_row) = symmetric_grid.pop() {
-			let mut row: Vec<_> = s_row.clone().into_iter().rev().collect();
-			row.append(&mut s_row);
-			grid.push(row);
-		}
-		for i in 1..=x {
-			grid.push(grid[x-i].clone());
-		}
-		GridSandpile::from_grid(GridType::Finite(FiniteGridType::Rectangular), Neighbourhood::VonNeumann, grid).unwrap()
+		let odometer = topple_rect_vn_es_optimized(&mut symmetric_grid);
+		let mut sandpile = GridSandpile::from_grid(GridType::Finite(FiniteGridType::Rectangular), Neighbourhood::VonNeumann, unfold_es(symmetric_grid)).unwrap();
+		sandpile.set_odometer(unfold_es(odometer));
+		sandpile
 	}
 
 	pub(super) fn neutral_plus_rect_vn_ee_optimized(x: usize, y: usize, plus: Cell) -> GridSandpile { // ee = even even
@@ -125,22 +323,53 @@ impl<'a> FiniteGridSandpile<'a> {
 				*el = t + plus - *el;
 			}
 		}
-		topple_rect_vn_ee_optimized(&mut symmetric_grid);
-		let mut grid = Vec::new();
-		while let Some(mut s_row) = symmetric_grid.pop() {
-			let mut row: Vec<_> = s_row.clone().into_iter().rev().collect();
-			row.append(&mut s_row);
-			grid.push(row);
-		}
-		for i in 1..=y {
-			grid.push(grid[y-i].clone());
+		let odometer = topple_rect_vn_ee_optimized(&mut symmetric_grid);
+		let mut sandpile = GridSandpile::from_grid(GridType::Finite(FiniteGridType::Rectangular), Neighbourhood::VonNeumann, unfold_ee(symmetric_grid)).unwrap();
+		sandpile.set_odometer(unfold_ee(odometer));
+		sandpile
+	}
+}
+
+impl GridSandpile {
+	fn set_odometer(&mut self, odometer: Grid) {
+		self.last_topple = odometer.iter().flatten().sum::<Cell>() as u64;
+		self.odometer = Some(odometer);
+	}
+}
+
+// The triangle j <= i of the bottom right quarter of an even square is reflected to the whole square.
+#[allow(clippy::needless_range_loop)]
+fn unfold_es(mut symmetric_grid: Grid) -> Grid {
+	let x = symmetric_grid.len();
+	for i in 0..x {
+		for j in i+1..x {
+			let sc = symmetric_grid[j][i];
+			symmetric_grid[i].push(sc);
 		}
-		GridSandpile::from_grid(GridType::Finite(FiniteGridType::Rectangular), Neighbourhood::VonNeumann, grid).unwrap()
 	}
+	unfold_ee(symmetric_grid)
 }
 
-pub(super) fn topple_rect_vn_es_optimized(grid: &mut Grid) {
+// The bottom right quarter of an even rectangle is reflected to the whole rectangle.
+fn unfold_ee(mut symmetric_grid: Grid) -> Grid {
+	let y = symmetric_grid.len();
+	let mut grid = Vec::new();
+	while let Some(mut s_row) = symmetric_grid.pop() {
+		let mut row: Vec<_> = s_row.clone().into_iter().rev().collect();
+		row.append(&mut s_row);
+		grid.push(row);
+	}
+	for i in 1..=y {
+		grid.push(grid[y-i].clone());
+	}
+	grid
+}
+
+// Returns the odometer.
+#[allow(clippy::needless_range_loop)]
+pub(super) fn topple_rect_vn_es_optimized(grid: &mut Grid) -> Grid {
 	let x = grid.len();
+	let mut odometer: Grid = (0..x).map(|i| vec![0; i+1]).collect();
 	assert!(x > 2);
 	let mut ex_table = Vec::new();
 	for i in 0..x {
@@ -188,6 +417,7 @@ pub(super) fn topple_rect_vn_es_optimized(grid: &mut Grid) {
 				continue;
 			}
 			grid[i][j] %= 4;
+			odometer[i][j] += d;
 			let topple_to = match (i, j) {
 				(0, 0) => &[(0, 0), (0, 0), (1, 0)][..],
 				(1, 0) => &[(1, 0), (2, 0), (0, 0), (0, 0), (1, 1), (1, 1)][..],
@@ -202,10 +432,8 @@ pub(super) fn topple_rect_vn_es_optimized(grid: &mut Grid) {
 			};
 			for &(ti, tj) in topple_to {
 				grid[ti][tj] += d;
-				if let Some(p) = ex2.last() {
-					if *p == (ti, tj) {
-						continue
-					}
+				if ex2.last() == Some(&(ti, tj)) {
+					continue
 				}
 				if grid[ti][tj] >= 4 {
 					ex_table[ti][tj] = true;
@@ -232,11 +460,14 @@ pub(super) fn topple_rect_vn_es_optimized(grid: &mut Grid) {
 		(excessive, ex2) = (ex2, excessive);
 		ex2.clear();
 	}
+	odometer
 }
 
-pub(super) fn topple_rect_vn_ee_optimized(grid: &mut Grid) {
+// Returns the odometer.
+pub(super) fn topple_rect_vn_ee_optimized(grid: &mut Grid) -> Grid {
 	let y = grid.len();
 	let x = grid[0].len();
+	let mut odometer = vec![vec![0; x]; y];
 	assert!(x > 1 && y > 1);
 	let mut ex_table = vec![vec![true; x]; y];
 	let mut use_vec = false;
@@ -280,6 +511,7 @@ pub(super) fn topple_rect_vn_ee_optimized(grid: &mut Grid) {
 				continue;
 			}
 			grid[i][j] %= 4;
+			odometer[i][j] += d;
 			let topple_to = match (i, j) {
 				(0, 0) => &[(0, 0), (0, 0), (1, 0), (0, 1)][..],
 				(i, j) if i == y-1 && j == x-1 => &[(y-1, x-2), (y-2, x-1)][..],
@@ -293,10 +525,8 @@ pub(super) fn topple_rect_vn_ee_optimized(grid: &mut Grid) {
 			};
 			for &(ti, tj) in topple_to {
 				grid[ti][tj] += d;
-				if let Some(p) = ex2.last() {
-					if *p == (ti, tj) {
-						continue
-					}
+				if ex2.last() == Some(&(ti, tj)) {
+					continue
 				}
 				if grid[ti][tj] >= 4 {
 					ex_table[ti][tj] = true;
@@ -323,4 +553,5 @@ pub(super) fn topple_rect_vn_ee_optimized(grid: &mut Grid) {
 		(excessive, ex2) = (ex2, excessive);
 		ex2.clear();
 	}
+	odometer
 }
@@ -0,0 +1,29 @@
+use super::*;
+
+// SplitMix64, a small and fast generator, so that random sandpiles are reproducible from a seed without dependencies.
+pub(super) struct SplitMix64(u64);
+
+impl SplitMix64 {
+	pub(super) fn new(seed: u64) -> SplitMix64 {
+		SplitMix64(seed)
+	}
+
+	pub(super) fn next_u64(&mut self) -> u64 {
+		self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+		let mut z = self.0;
+		z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+		z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+		z ^ (z >> 31)
+	}
+
+	// Uniform in 0..n, rejecting the numbers above the largest multiple of n.
+	pub(super) fn below(&mut self, n: Cell) -> Cell {
+		let zone = Cell::MAX - Cell::MAX % n;
+		loop {
+			let r = (self.next_u64() as Cell) << 64 | self.next_u64() as Cell;
+			if r < zone {
+				return r % n
+			}
+		}
+	}
+}
@@ -0,0 +1,369 @@
+use super::*;
+
+use std::collections::HashSet;
+
+use num_bigint::{BigInt, BigUint};
+use num_integer::Integer;
+use num_traits::{One, Signed, Zero};
+
+// Sparse row: (column, entry) pairs sorted by column, with no zero entries.
+type Row = Vec<(usize, BigInt)>;
+
+impl Graph {
+	// Invariant factors of the sandpile group, that is, the Smith normal form of the reduced Laplacian.
+	// Factors equal to 1 are omitted, so the trivial group gives an empty list. Each factor divides the next one.
+	pub fn invariant_factors(&self) -> Vec<BigUint> {
+		invariant_factors(dense_core(self.reduced_laplacian(), 0))
+	}
+
+	// Order of the sandpile group, that is, the number of spanning trees directed to the sink, as the determinant of the reduced Laplacian.
+	pub fn group_order(&self) -> BigUint {
+		let core = dense_core(self.reduced_laplacian(), 0);
+		if core.is_empty() {
+			return BigUint::one()
+		}
+		let n = core.len();
+		determinant(core, n).0.abs().to_biguint().unwrap()
+	}
+
+	// Order of the configuration in the sandpile group, which needn't be recurrent or stable: the least k such that
+	// k·config = L^T·v for an integer v, L being the reduced Laplacian, since toppling a vertex subtracts its row of L.
+	// It is the common denominator of (L^T)^-1·config.
+	pub fn element_order(&self, config: &[Cell]) -> BigUint {
+		self.denominator(config.iter().map(|&c| BigInt::from(c)))
+	}
+
+	// Whether two configurations, which may be unstable or negative, are equivalent in the sandpile group, that is,
+	// their difference is L^T·v for an integer v, as for the order. Nothing topples, so the chips may be arbitrarily many.
+	// The chips of the sink are ignored.
+	pub fn equivalent(&self, a: &[BigInt], b: &[BigInt]) -> Result<bool, SandpileError> {
+		for c in [a, b] {
+			if c.len() != self.len() {
+				return Err(SandpileError::UnequalLengths(self.len(), c.len()))
+			}
+		}
+		Ok(self.denominator(a.iter().zip(b).map(|(a, b)| a - b)).is_one())
+	}
+
+	// The least k such that k·config is in the image of L^T, config being given for all the vertices.
+	fn denominator(&self, config: impl Iterator<Item = BigInt>) -> BigUint {
+		let n = self.len() - 1;
+		let mut rows = vec![Vec::new(); n];
+		for (v, row) in self.reduced_laplacian().into_iter().enumerate() {
+			for (u, a) in row {
+				rows[u].push((v, a));
+			}
+		}
+		let config = config.enumerate().filter(|&(v, _)| v != self.sink()).map(|(_, c)| c);
+		for (row, c) in rows.iter_mut().zip(config) {
+			if !c.is_zero() {
+				row.push((n, c));
+			}
+		}
+		let mut core = dense_core(rows, 1);
+		let m = core.len();
+		if m == 0 {
+			return BigUint::one()
+		}
+		// Cramer's rule: D·v is integer, D being the determinant, so the back substitution only has exact divisions.
+		bareiss(&mut core, m).expect("The reduced Laplacian is nonsingular.");
+		let d = core[m-1][m-1].clone();
+		let mut dv = vec![BigInt::zero(); m];
+		for i in (0..m).rev() {
+			let s: BigInt = (i+1..m).map(|j| &core[i][j] * &dv[j]).sum();
+			dv[i] = (&d * &core[i][m] - s) / &core[i][i];
+		}
+		let g = dv.iter().fold(d.clone(), |g, x| g.gcd(x));
+		(d / g).abs().to_biguint().unwrap()
+	}
+
+	fn reduced_laplacian(&self) -> Vec<Row> {
+		let vertices: Vec<_> = (0..self.len()).filter(|&v| v != self.sink()).collect();
+		let mut index = vec![usize::MAX; self.len()];
+		for (k, &v) in vertices.iter().enumerate() {
+			index[v] = k;
+		}
+		vertices.iter().map(|&v| {
+			let mut row: Row = Vec::new();
+			let mut diagonal = BigInt::from(self.degree(v) - self.multiplicity(v, v));
+			for &(u, m) in self.edges(v) {
+				if u == v || u == self.sink() {
+					continue;
+				}
+				row.push((index[u], -BigInt::from(m)));
+			}
+			row.push((index[v], BigInt::zero()));
+			row.sort_by_key(|&(k, _)| k);
+			let k = row.binary_search_by_key(&index[v], |&(k, _)| k).unwrap();
+			std::mem::swap(&mut row[k].1, &mut diagonal);
+			row
+		}).collect()
+	}
+}
+
+// Unit pivots of a nonsingular square integer matrix are eliminated while it is sparse, which changes neither the determinant up to sign
+// nor the invariant factors other than 1. Every row is pivoted on its unit entry with the largest column,
+// which for grids numbered row by row is the transfer matrix method and leaves a dense core of about the size of the grid's side.
+// The columns after the first n, like the right-hand side of a system, are never pivoted on and are appended to the core.
+fn dense_core(mut rows: Vec<Row>, extra: usize) -> Vec<Vec<BigInt>> {
+	let n = rows.len();
+	let mut cols = vec![HashSet::new(); n + extra];
+	for (r, row) in rows.iter().enumerate() {
+		for (c, _) in row {
+			cols[*c].insert(r);
+		}
+	}
+	let mut row_alive = vec![true; n];
+	let mut col_alive = vec![true; n];
+	col_alive.resize(n + extra, false);
+	let mut eliminated = true;
+	while eliminated {
+		eliminated = false;
+		for p in 0..n {
+			if !row_alive[p] {
+				continue;
+			}
+			let Some(k) = rows[p].iter().rposition(|(c, a)| col_alive[*c] && a.abs().is_one()) else {continue};
+			eliminated = true;
+			let pivot_row = std::mem::take(&mut rows[p]);
+			let (c, s) = (pivot_row[k].0, &pivot_row[k].1);
+			row_alive[p] = false;
+			col_alive[c] = false;
+			let targets: Vec<_> = cols[c].iter().copied().filter(|&r| r != p).collect();
+			for r in targets {
+				let f = &rows[r][rows[r].binary_search_by_key(&c, |&(k, _)| k).unwrap()].1 * s;
+				let row = subtract_multiple(&rows[r], &pivot_row, &f);
+				for (col, _) in &rows[r] {
+					cols[*col].remove(&r);
+				}
+				for (col, _) in &row {
+					cols[*col].insert(r);
+				}
+				rows[r] = row;
+			}
+			for (col, _) in &pivot_row {
+				cols[*col].remove(&p);
+			}
+		}
+	}
+	// The remaining rows only have entries in the remaining columns.
+	let core_cols: Vec<_> = (0..n).filter(|&c| col_alive[c]).chain(n..n + extra).collect();
+	let mut col_index = vec![usize::MAX; n + extra];
+	for (k, &c) in core_cols.iter().enumerate() {
+		col_index[c] = k;
+	}
+	let m = core_cols.len();
+	(0..n).filter(|&r| row_alive[r]).map(|r| {
+		let mut row = vec![BigInt::zero(); m];
+		for (c, a) in &rows[r] {
+			row[col_index[*c]] = a.clone();
+		}
+		row
+	}).collect()
+}
+
+// The determinant D of the core is computed exactly together with a few of its minors of size one less.
+// All factors but the last divide those minors, so they are found from the Smith normal form modulo the gcd of D and the minors,
+// and the last factor is D divided by the others.
+fn invariant_factors(core: Vec<Vec<BigInt>>) -> Vec<BigUint> {
+	let m = core.len();
+	if m == 0 {
+		return Vec::new();
+	}
+	// The cokernel of the core is annihilated by d, and all its factors but the last divide any (m-1)-minor.
+	let (d, minors) = determinant(core.iter().enumerate().map(|(i, row)| {
+		let mut row = row.clone();
+		row.extend((0..m.min(MINORS)).map(|j| if i == j {BigInt::one()} else {BigInt::zero()}));
+		row
+	}).collect(), m);
+	let d = d.abs();
+	let modulus = minors.iter().fold(d.clone(), |g, x| g.gcd(x));
+	let mut factors = smith_mod(core, &modulus);
+	let rest = factors[..m-1].iter().product::<BigInt>();
+	factors[m-1] = &d / rest;
+	factors.into_iter().filter(|f| !f.is_one()).map(|f| f.to_biguint().unwrap()).collect()
+}
+
+// row - f·pivot_row.
+fn subtract_multiple(row: &Row, pivot_row: &Row, f: &BigInt) -> Row {
+	let mut result = Vec::with_capacity(row.len() + pivot_row.len());
+	let (mut a, mut b) = (row.iter().peekable(), pivot_row.iter().peekable());
+	loop {
+		let entry = match (a.peek(), b.peek()) {
+			(None, None) => break,
+			(Some(&&(i, ref x)), Some(&&(j, _))) if i < j => {a.next(); (i, x.clone())},
+			(Some(&&(i, ref x)), None) => {a.next(); (i, x.clone())},
+			(Some(&&(i, ref x)), Some(&&(j, ref y))) if i == j => {a.next(); b.next(); (i, x - f * y)},
+			(_, Some(&&(j, ref y))) => {b.next(); (j, -(f * y))},
+		};
+		if !entry.1.is_zero() {
+			result.push(entry);
+		}
+	}
+	result
+}
+
+// Columns of the identity appended to the core to get some of its (m-1)-minors along with the determinant.
+const MINORS: usize = 4;
+
+// Fraction-free Gaussian elimination by Bareiss on a square matrix of size n with extra columns appended.
+// Returns the determinant and the entries of the last row in the extra columns,
+// which are the determinants of the matrix with its last column replaced by the respective extra column.
+fn determinant(mut a: Vec<Vec<BigInt>>, n: usize) -> (BigInt, Vec<BigInt>) {
+	let Some(sign) = bareiss(&mut a, n) else {
+		return (BigInt::zero(), Vec::new())
+	};
+	let minors = a[n-1][n..].iter().map(|x| &sign * x).collect();
+	(sign * &a[n-1][n-1], minors)
+}
+
+// Brings the matrix to the upper triangular form in place, a[k][k] being the k-th leading principal minor of the matrix with rows permuted.
+// Returns the sign of the permutation, or None for a singular matrix.
+fn bareiss(a: &mut [Vec<BigInt>], n: usize) -> Option<BigInt> {
+	let width = a[0].len();
+	let mut sign = BigInt::one();
+	let mut prev = BigInt::one();
+	for k in 0..n {
+		let p = (k..n).find(|&i| !a[i][k].is_zero())?;
+		if p != k {
+			a.swap(p, k);
+			sign = -sign;
+		}
+		let (done, rest) = a.split_at_mut(k + 1);
+		let pivot_row = &done[k];
+		for row in rest {
+			for j in k+1..width {
+				row[j] = (&row[j] * &pivot_row[k] - &row[k] * &pivot_row[j]) / &prev;
+			}
+		}
+		prev = a[k][k].clone();
+	}
+	Some(sign)
+}
+
+// Diagonal of the Smith normal form modulo d, that is, the gcds of the invariant factors with d, as a divisibility chain.
+#[allow(clippy::needless_range_loop)]
+fn smith_mod(mut a: Vec<Vec<BigInt>>, d: &BigInt) -> Vec<BigInt> {
+	let n = a.len();
+	for row in &mut a {
+		for x in row {
+			*x = x.mod_floor(d);
+		}
+	}
+	let mut diagonal = Vec::with_capacity(n);
+	for t in 0..n {
+		// A pivot invertible modulo d clears its column and leaves the factor 1, so such pivots are taken first.
+		if let Some(pi) = (t..n).find(|&i| !a[i][t].is_zero() && a[i][t].gcd(d).is_one()) {
+			a.swap(t, pi);
+			let inverse = a[t][t].extended_gcd(d).x;
+			let (top, rest) = a[t..].split_first_mut().unwrap();
+			for row in rest {
+				if row[t].is_zero() {
+					continue;
+				}
+				let f = (&row[t] * &inverse).mod_floor(d);
+				for k in t..n {
+					row[k] = (&row[k] - &f * &top[k]).mod_floor(d);
+				}
+			}
+			diagonal.push(BigInt::one());
+			continue;
+		}
+		let Some((pi, pj)) = (t..n).flat_map(|i| (t..n).map(move |j| (i, j))).find(|&(i, j)| !a[i][j].is_zero()) else {
+			diagonal.resize(n, d.clone());
+			break
+		};
+		a.swap(t, pi);
+		for row in &mut a {
+			row.swap(t, pj);
+		}
+		loop {
+			for i in t+1..n {
+				if a[i][t].is_zero() {
+					continue;
+				}
+				// The pivot row must stay as it is unless the pivot decreases, otherwise the two passes might undo each other forever.
+				if a[i][t].is_multiple_of(&a[t][t]) {
+					let (top, rest) = a[t..].split_first_mut().unwrap();
+					let row = &mut rest[i-t-1];
+					let f = &row[t] / &top[t];
+					for k in t..n {
+						row[k] = (&row[k] - &f * &top[k]).mod_floor(d);
+					}
+					continue;
+				}
+				let e = a[t][t].extended_gcd(&a[i][t]);
+				let (u, v) = (&a[t][t] / &e.gcd, &a[i][t] / &e.gcd);
+				for k in t..n {
+					let (x, y) = (&a[t][k], &a[i][k]);
+					let top = (&e.x * x + &e.y * y).mod_floor(d);
+					a[i][k] = (&u * y - &v * x).mod_floor(d);
+					a[t][k] = top;
+				}
+			}
+			for j in t+1..n {
+				if a[t][j].is_zero() {
+					continue;
+				}
+				if a[t][j].is_multiple_of(&a[t][t]) {
+					let f = &a[t][j] / &a[t][t];
+					for row in a[t..].iter_mut() {
+						row[j] = (&row[j] - &f * &row[t]).mod_floor(d);
+					}
+					continue;
+				}
+				let e = a[t][t].extended_gcd(&a[t][j]);
+				let (u, v) = (&a[t][t] / &e.gcd, &a[t][j] / &e.gcd);
+				for row in a[t..].iter_mut() {
+					let (x, y) = (&row[t], &row[j]);
+					let left = (&e.x * x + &e.y * y).mod_floor(d);
+					row[j] = (&u * y - &v * x).mod_floor(d);
+					row[t] = left;
+				}
+			}
+			if (t+1..n).all(|i| a[i][t].is_zero()) {
+				break;
+			}
+		}
+		diagonal.push(a[t][t].gcd(d));
+	}
+	// The diagonal is made a divisibility chain without changing the group.
+	for i in 0..n {
+		for j in i+1..n {
+			let (g, l) = (diagonal[i].gcd(&diagonal[j]), diagonal[i].lcm(&diagonal[j]));
+			diagonal[i] = g;
+			diagonal[j] = l;
+		}
+	}
+	diagonal
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn factors(grid_type: FiniteGridType, neighbourhood: Neighbourhood, (x, y): (usize, usize)) -> Vec<u64> {
+		FiniteGridSandpile::invariant_factors(grid_type, neighbourhood, (x, y)).iter().map(|f| f.try_into().unwrap()).collect()
+	}
+
+	#[test]
+	fn invariant_factors() {
+		assert_eq!(factors(FiniteGridType::Rectangular, Neighbourhood::VonNeumann, (1, 1)), [4]);
+		assert_eq!(factors(FiniteGridType::Rectangular, Neighbourhood::VonNeumann, (2, 1)), [15]);
+		assert_eq!(factors(FiniteGridType::Rectangular, Neighbourhood::VonNeumann, (2, 2)), [8, 24]);
+		assert_eq!(factors(FiniteGridType::Rectangular, Neighbourhood::VonNeumann, (3, 3)), [4, 112, 224]);
+		assert_eq!(factors(FiniteGridType::Toroidal, Neighbourhood::Moore, (4, 4)), [2, 6, 24, 240, 240, 480, 480]);
+		// The order of the group is the number of spanning trees, and the order of every element divides the last factor.
+		let graph = Rc::new(FiniteGridSandpile::graph(FiniteGridType::Toroidal, Neighbourhood::VonNeumann, (3, 4)));
+		let f: Vec<u64> = graph.invariant_factors().iter().map(|f| f.try_into().unwrap()).collect();
+		assert_eq!(f.iter().product::<u64>(), 367500);
+		let a = GraphSandpile::from_config(Rc::clone(&graph), (0..12).map(|v| if v == 0 {0} else {3}).collect()).unwrap();
+		assert_eq!(f.last().unwrap() % a.order_by_addition(), 0);
+		assert_eq!(factors(FiniteGridType::Toroidal, Neighbourhood::VonNeumann, (3, 4)), f);
+		// Cells outside the mask don't count.
+		let mask = mask_from_string("x.x\n...\nx.x").unwrap();
+		let plus: Vec<u64> = FiniteGridSandpile::invariant_factors_masked(FiniteGridType::Rectangular, Neighbourhood::VonNeumann, &mask).unwrap()
+			.iter().map(|f| f.try_into().unwrap()).collect();
+		assert_eq!(plus, [4, 4, 48]);
+	}
+}
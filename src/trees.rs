@@ -0,0 +1,260 @@
+use num_bigint::BigUint;
+
+// Counting spanning trees of rectangles and tori by the product formulas over the eigenvalues of the Laplacian.
+// Eigenvalues are sums of cosines, so the products are computed modulo primes p ≡ 1 (mod N), where cos(2πk/N) = (ω^k + ω^-k)/2
+// for a primitive N-th root of unity ω modulo p, and the exact number is then recovered by the Chinese remainder theorem.
+// The number of primes is given by Hadamard's inequality: the determinant of the (reduced) Laplacian is at most the product of degrees.
+// The grid is m cells wide and n cells high, and the product runs over the rows, so n should be the smaller side.
+pub(super) fn grid_trees(torus: bool, moore: bool, (m, n): (usize, usize)) -> BigUint {
+	let (m, n) = (m.max(n) as u64, m.min(n) as u64);
+	let order = if torus {n} else {2*(n+1)};
+	let bits = (m*n) as f64 * if moore {3.0} else {2.0};
+	let mut primes = Vec::new();
+	let mut residues = Vec::new();
+	let mut covered = 0.0;
+	let step = if order % 2 == 0 {order} else {2*order};	// Only odd primes.
+	let mut candidate = (1 << 62) / step * step + 1;
+	while covered <= bits {
+		candidate -= step;
+		let md = Modulus::new(candidate);
+		if !md.is_prime() {
+			continue;
+		}
+		primes.push(candidate);
+		covered += (candidate as f64).log2().floor();
+		residues.push(md.from(trees_mod(&md, order, torus, moore, (m, n))));
+	}
+	garner(&primes, &residues)
+}
+
+// The rectangle has the eigenvalues 4 - 2cos(πj/(m+1)) - 2cos(πk/(n+1)) with 1 ≤ j ≤ m, 1 ≤ k ≤ n, and the torus
+// 4 - 2cos(2πj/m) - 2cos(2πk/n) with 0 ≤ j < m, 0 ≤ k < n, the zero one excluded and the product divided by mn.
+// For the Moore neighbourhood, 4 - 2a - 2b becomes 9 - (1 + 2a)(1 + 2b).
+// The product over j is a Chebyshev polynomial, that is, a Lucas sequence: with μ = 1 + 2cos(·), it is U_{m+1}(4 - 2cos(·), 1)
+// and U_{m+1}(9 - μ, μ²) on the rectangle, V_m(4 - 2cos(·), 1) - 2 and V_m(9 - μ, μ²) - 2μ^m on the torus.
+// The result is in the Montgomery form.
+fn trees_mod(md: &Modulus, order: u64, torus: bool, moore: bool, (m, n): (u64, u64)) -> u64 {
+	let omega = md.root_of_unity(order);
+	let omega_inverse = md.pow(omega, order - 1);
+	let half = md.to(md.p/2 + 1);
+	let (two, four, nine) = (md.to(2), md.to(4), md.to(9));
+	let (mut w, mut w_inverse) = (md.one(), md.one());
+	let mut product = md.one();
+	for _ in 1..if torus {n} else {n+1} {
+		w = md.mul(w, omega);
+		w_inverse = md.mul(w_inverse, omega_inverse);
+		let cos = md.mul(md.add(w, w_inverse), half);
+		let factor = match (torus, moore) {
+			(false, false) => md.lucas(md.sub(four, md.add(cos, cos)), md.one(), m+1).0,
+			(false, true) => {
+				let mu = md.add(md.one(), md.add(cos, cos));
+				md.lucas(md.sub(nine, mu), md.mul(mu, mu), m+1).0
+			},
+			(true, false) => md.sub(md.lucas(md.sub(four, md.add(cos, cos)), md.one(), m).1, two),
+			(true, true) => {
+				let mu = md.add(md.one(), md.add(cos, cos));
+				let v = md.lucas(md.sub(nine, mu), md.mul(mu, mu), m).1;
+				md.sub(v, md.mul(two, md.pow(mu, m)))
+			},
+		};
+		product = md.mul(product, factor);
+	}
+	if torus {
+		// The zero eigenvalue's row gives m² · 3^(m-1) for the Moore neighbourhood.
+		product = md.mul(product, md.mul(md.to(m), md.inverse(md.to(n))));
+		if moore {
+			product = md.mul(product, md.pow(md.to(3), m - 1));
+		}
+	}
+	product
+}
+
+// Mixed radix conversion: x = d_0 + p_0·(d_1 + p_1·(d_2 + …)).
+fn garner(primes: &[u64], residues: &[u64]) -> BigUint {
+	let k = primes.len();
+	let moduli: Vec<_> = primes.iter().map(|&p| Modulus::new(p)).collect();
+	// For every prime p_i, the value of the digits so far and the product of the primes so far, modulo p_i.
+	let mut values = vec![0; k];
+	let mut products: Vec<_> = moduli.iter().map(|md| md.one()).collect();
+	let mut digits = Vec::with_capacity(k);
+	for j in 0..k {
+		let md = &moduli[j];
+		let d = md.mul(md.sub(md.to(residues[j]), values[j]), md.inverse(products[j]));
+		let d = md.from(d);
+		digits.push(d);
+		for i in j+1..k {
+			let mi = &moduli[i];
+			values[i] = mi.add(values[i], mi.mul(mi.to(d), products[i]));
+			products[i] = mi.mul(products[i], mi.to(primes[j]));
+		}
+	}
+	let mut x = BigUint::ZERO;
+	for j in (0..k).rev() {
+		x = x * primes[j] + digits[j];
+	}
+	x
+}
+
+// Arithmetic modulo an odd p < 2^62 in the Montgomery form a·2^64.
+struct Modulus {
+	p: u64,
+	p_neg_inverse: u64,	// -1/p modulo 2^64.
+	r2: u64,	// 2^128 modulo p.
+}
+
+impl Modulus {
+	fn new(p: u64) -> Modulus {
+		let mut inverse = p;	// Correct modulo 8, and every Newton step doubles the number of bits.
+		for _ in 0..5 {
+			inverse = inverse.wrapping_mul(2u64.wrapping_sub(p.wrapping_mul(inverse)));
+		}
+		let r = ((1u128 << 64) % p as u128) as u64;
+		Modulus {
+			p,
+			p_neg_inverse: inverse.wrapping_neg(),
+			r2: (r as u128 * r as u128 % p as u128) as u64,
+		}
+	}
+
+	fn reduce(&self, t: u128) -> u64 {
+		let m = (t as u64).wrapping_mul(self.p_neg_inverse);
+		let u = ((t + m as u128 * self.p as u128) >> 64) as u64;
+		if u >= self.p {u - self.p} else {u}
+	}
+
+	fn mul(&self, a: u64, b: u64) -> u64 {
+		self.reduce(a as u128 * b as u128)
+	}
+
+	fn to(&self, a: u64) -> u64 {
+		self.mul(a, self.r2)
+	}
+
+	fn from(&self, a: u64) -> u64 {
+		self.reduce(a as u128)
+	}
+
+	fn one(&self) -> u64 {
+		self.to(1)
+	}
+
+	fn add(&self, a: u64, b: u64) -> u64 {
+		let s = a + b;
+		if s >= self.p {s - self.p} else {s}
+	}
+
+	fn sub(&self, a: u64, b: u64) -> u64 {
+		if a >= b {a - b} else {a + self.p - b}
+	}
+
+	fn pow(&self, mut a: u64, mut e: u64) -> u64 {
+		let mut result = self.one();
+		while e > 0 {
+			if e & 1 == 1 {
+				result = self.mul(result, a);
+			}
+			a = self.mul(a, a);
+			e >>= 1;
+		}
+		result
+	}
+
+	// Only for prime p.
+	fn inverse(&self, a: u64) -> u64 {
+		self.pow(a, self.p - 2)
+	}
+
+	// Deterministic Miller–Rabin test for 64-bit numbers.
+	fn is_prime(&self) -> bool {
+		let p = self.p;
+		for q in [3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37] {
+			if p.is_multiple_of(q) {
+				return p == q
+			}
+		}
+		let s = (p - 1).trailing_zeros();
+		let d = (p - 1) >> s;
+		let (one, minus_one) = (self.one(), self.sub(0, self.one()));
+		'bases: for a in [2, 325, 9375, 28178, 450775, 9780504, 1795265022] {
+			let a = self.to(a);
+			if a == 0 {
+				continue;
+			}
+			let mut x = self.pow(a, d);
+			if x == one || x == minus_one {
+				continue;
+			}
+			for _ in 1..s {
+				x = self.mul(x, x);
+				if x == minus_one {
+					continue 'bases;
+				}
+			}
+			return false
+		}
+		true
+	}
+
+	// A primitive root of unity of the given order, which must divide p - 1.
+	fn root_of_unity(&self, order: u64) -> u64 {
+		let mut factors = Vec::new();
+		let (mut rest, mut q) = (order, 2);
+		while rest > 1 {
+			if q * q > rest {
+				factors.push(rest);
+				break;
+			}
+			if rest % q == 0 {
+				factors.push(q);
+				while rest % q == 0 {
+					rest /= q;
+				}
+			}
+			q += 1;
+		}
+		(2..).map(|a| self.pow(self.to(a), (self.p - 1) / order))
+			.find(|&w| factors.iter().all(|&q| self.pow(w, order / q) != self.one()))
+			.unwrap()
+	}
+
+	// Lucas sequences (U_e, V_e) for U_0 = 0, U_1 = 1, V_0 = 2, V_1 = P, X_{k+1} = P·X_k - Q·X_{k-1}.
+	fn lucas(&self, p: u64, q: u64, e: u64) -> (u64, u64) {
+		let half = self.to(self.p/2 + 1);
+		let d = self.sub(self.mul(p, p), self.mul(self.to(4), q));
+		let (mut u, mut v, mut qk) = (0, self.to(2), self.one());
+		for bit in (0..64 - e.leading_zeros()).rev() {
+			// k → 2k
+			u = self.mul(u, v);
+			v = self.sub(self.mul(v, v), self.add(qk, qk));
+			qk = self.mul(qk, qk);
+			if e >> bit & 1 == 1 {
+				// k → k+1
+				let u1 = self.mul(self.add(self.mul(p, u), v), half);
+				v = self.mul(self.add(self.mul(d, u), self.mul(p, v)), half);
+				u = u1;
+				qk = self.mul(qk, q);
+			}
+		}
+		(u, v)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{FiniteGridType, FiniteGridSandpile, Neighbourhood};
+
+	#[test]
+	fn spanning_trees() {
+		for (grid_type, torus) in [(FiniteGridType::Rectangular, false), (FiniteGridType::Toroidal, true)] {
+			for (neighbourhood, moore) in [(Neighbourhood::VonNeumann, false), (Neighbourhood::Moore, true)] {
+				for (x, y) in [(1, 1), (1, 4), (3, 2), (4, 4), (5, 3), (6, 7)] {
+					let graph = FiniteGridSandpile::graph(grid_type, neighbourhood.clone(), (x, y));
+					assert_eq!(grid_trees(torus, moore, (x, y)), graph.group_order(), "{grid_type:?} {neighbourhood:?} {x}×{y}");
+				}
+			}
+		}
+		// The n×n rectangle with the sink is the planar dual of the (n+1)×(n+1) grid graph, OEIS A007341.
+		assert_eq!(grid_trees(false, false, (10, 10)).to_string(), "40325021721404118513276859513497679249183623593590784");
+	}
+}
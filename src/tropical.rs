@@ -0,0 +1,296 @@
+use super::*;
+
+use std::{
+	fs,
+	collections::{BTreeSet, HashMap},
+};
+
+use num_integer::Integer;
+
+// Affine function a·x + b·y + c of the column x and the row y, as (a, b, c).
+pub type Monomial = (i128, i128, i128);
+
+// A point with the rational coordinates (x/q, y/q) in the units of cells, q > 0, the fractions being in the lowest terms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Point {
+	pub x: i128,
+	pub y: i128,
+	pub q: i128,
+}
+
+impl Point {
+	fn new(x: i128, y: i128, q: i128) -> Point {
+		let g = x.gcd(&y).gcd(&q) * q.signum();
+		Point {x: x / g, y: y / g, q: q / g}
+	}
+}
+
+fn fraction(f: &mut fmt::Formatter, p: i128, q: i128) -> fmt::Result {
+	let g = p.gcd(&q) * q.signum();
+	match (p / g, q / g) {
+		(p, 1) => write!(f, "{p}"),
+		(p, q) => write!(f, "{p}/{q}"),
+	}
+}
+
+impl fmt::Display for Point {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		fraction(f, self.x, self.q)?;
+		write!(f, " ")?;
+		fraction(f, self.y, self.q)
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TropicalEdge {
+	pub ends: (usize, usize),	// Numbers of the vertices.
+	pub direction: (i128, i128),	// Primitive integer vector (dx, dy) from the first end to the second one.
+	pub weight: i128,
+}
+
+// The tropical curve of an odometer u: where u is not linear. In every face of the curve, u is an affine function with integer slopes,
+// and u is the minimum of these functions, so the curve is the corner locus of a tropical polynomial, with rational vertices.
+// An edge between two faces has the weight of the lattice length of the difference of their slopes.
+// The grid is framed by the sink, where u = 0, and the curve is cut by the frame: the ends on it are vertices too.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TropicalCurve {
+	size: (usize, usize),
+	faces: Vec<Monomial>,
+	vertices: Vec<Point>,
+	edges: Vec<TropicalEdge>,
+}
+
+impl fmt::Display for TropicalCurve {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let (d1, d2) = self.degree();
+		writeln!(f, "Degree: {d1} × {d2}")?;
+		writeln!(f, "Genus: {}", self.genus())?;
+		writeln!(f, "Vertices:")?;
+		for (n, v) in self.vertices.iter().enumerate() {
+			writeln!(f, "{n}: {v}")?;
+		}
+		writeln!(f, "Edges:")?;
+		for e in &self.edges {
+			write!(f, "{} {}: slope ", e.ends.0, e.ends.1)?;
+			match e.direction {
+				(0, _) => write!(f, "∞")?,
+				(dx, dy) => fraction(f, dy, dx)?,
+			}
+			writeln!(f, ", weight {}", e.weight)?;
+		}
+		Ok(())
+	}
+}
+
+impl TropicalCurve {
+	// The odometer of a rectangle with the sink all around. Its faces are found where u is affine on 3×3 squares,
+	// which leaves out the cells near the curve, where the discrete u deviates from the minimum of the affine functions.
+	pub fn from_odometer(odometer: &Grid) -> TropicalCurve {
+		let (x, y) = (odometer[0].len() as isize, odometer.len() as isize);
+		let u = |i: isize, j: isize| if (0..y).contains(&i) && (0..x).contains(&j) {odometer[i as usize][j as usize] as i128} else {0};
+		let mut faces = BTreeSet::new();
+		for i in -1..=y {
+			for j in -1..=x {
+				let (a, b) = (u(i, j+1) - u(i, j), u(i+1, j) - u(i, j));
+				if (-1..=1).all(|di| (-1..=1).all(|dj| u(i+di, j+dj) == u(i, j) + a*dj as i128 + b*di as i128)) {
+					faces.insert((a, b, u(i, j) - a*j as i128 - b*i as i128));
+				}
+			}
+		}
+		let faces: Vec<_> = faces.into_iter().collect();
+		let (x, y) = (x as i128, y as i128);
+		// The frame x = -1, x = X, y = -1, y = Y as constraints α·x + β·y + γ <= 0.
+		let frame = [(-1, 0, -1), (1, 0, -x), (0, -1, -1), (0, 1, -y)];
+		let mut vertices = Vec::new();
+		let mut index = HashMap::new();
+		let mut edges = Vec::new();
+		for k in 0..faces.len() {
+			for l in k+1..faces.len() {
+				let Some((p, q)) = Self::segment(&faces, (k, l), &frame) else {
+					continue;
+				};
+				let mut vertex = |p: Point| *index.entry(p).or_insert_with(|| {
+					vertices.push(p);
+					vertices.len() - 1
+				});
+				let ends = (vertex(p), vertex(q));
+				let (da, db) = (faces[k].0 - faces[l].0, faces[k].1 - faces[l].1);
+				let weight = da.gcd(&db);
+				edges.push(TropicalEdge {ends, direction: (-db / weight, da / weight), weight});
+			}
+		}
+		// A vertex inside an edge splits it.
+		let mut e = 0;
+		while e < edges.len() {
+			let (p, q) = (vertices[edges[e].ends.0], vertices[edges[e].ends.1]);
+			let inner = (0..vertices.len()).find(|&v| {
+				let r = vertices[v];
+				let (px, py, rx, ry) = (p.x * q.q, p.y * q.q, r.x * q.q, r.y * q.q);
+				let (qx, qy) = (q.x * p.q, q.y * p.q);
+				// Everything multiplied by p.q·q.q, with r taken along the edge as p + s·(q - p), 0 < s < 1.
+				let (dx, dy) = (qx - px, qy - py);
+				let (sx, sy) = (rx * p.q - px * r.q, ry * p.q - py * r.q);
+				let s = sx * dx + sy * dy;
+				sx * dy == sy * dx && s > 0 && s < (dx * dx + dy * dy) * r.q
+			});
+			if let Some(v) = inner {
+				let edge = edges[e].clone();
+				edges[e].ends.1 = v;
+				edges.push(TropicalEdge {ends: (v, edge.ends.1), ..edge});
+			} else {
+				e += 1;
+			}
+		}
+		TropicalCurve {size: (x as usize, y as usize), faces, vertices, edges}
+	}
+
+	// The segment where faces k and l are both minimal, as its ends from the first to the second one along the direction
+	// of the edge. None if it is empty or a point.
+	fn segment(faces: &[Monomial], (k, l): (usize, usize), frame: &[Monomial]) -> Option<(Point, Point)> {
+		let (fk, fl) = (faces[k], faces[l]);
+		// The line A·x + B·y + C = 0 is p(t) = (-A·C, -B·C)/n + t·(-B, A), n = A² + B².
+		let (a, b, c) = (fk.0 - fl.0, fk.1 - fl.1, fk.2 - fl.2);
+		let n = a*a + b*b;
+		// The bounds on t as fractions with positive denominators.
+		let (mut lo, mut hi) = (None::<(i128, i128)>, None::<(i128, i128)>);
+		let constraints = faces.iter().enumerate().filter(|&(m, _)| m != k && m != l)
+			.map(|(_, fm)| (fk.0 - fm.0, fk.1 - fm.1, fk.2 - fm.2, Some(fm)))
+			.chain(frame.iter().map(|&(al, be, ga)| (al, be, ga, None)));
+		for (al, be, ga, fm) in constraints {
+			// n·(α·x + β·y + γ) along the line is g + t·n·s.
+			let g = -c * (al*a + be*b) + ga*n;
+			let s = -al*b + be*a;
+			if s == 0 {
+				if g > 0 {
+					return None
+				}
+				// Face m equals k and l on the whole line: the edge is there only if m is never the least,
+				// that is, its slopes are between theirs.
+				if g == 0 && let Some(fm) = fm && (fm.0 - fk.0) * (fl.0 - fm.0) + (fm.1 - fk.1) * (fl.1 - fm.1) <= 0 {
+					return None
+				}
+				continue;
+			}
+			let bound = if s > 0 {(-g, n*s)} else {(g, -n*s)};
+			if s > 0 {
+				if hi.is_none_or(|h| bound.0 * h.1 < h.0 * bound.1) {
+					hi = Some(bound);
+				}
+			} else if lo.is_none_or(|l| bound.0 * l.1 > l.0 * bound.1) {
+				lo = Some(bound);
+			}
+		}
+		// The frame bounds the line on both sides.
+		let (lo, hi) = (lo?, hi?);
+		if lo.0 * hi.1 >= hi.0 * lo.1 {
+			return None
+		}
+		let point = |(t, d): (i128, i128)| Point::new(-a*c*d - t*b*n, -b*c*d + t*a*n, n*d);
+		Some((point(lo), point(hi)))
+	}
+
+	pub fn faces(&self) -> &[Monomial] {
+		&self.faces
+	}
+
+	pub fn vertices(&self) -> &[Point] {
+		&self.vertices
+	}
+
+	pub fn edges(&self) -> &[TropicalEdge] {
+		&self.edges
+	}
+
+	// The size of the least rectangle holding the slopes of the faces, that is, the Newton polygon, as for a curve
+	// on the product of two projective lines: how many times the curve meets a generic horizontal and vertical line.
+	pub fn degree(&self) -> (i128, i128) {
+		if self.faces.is_empty() {
+			return (0, 0)
+		}
+		let (a0, a1) = (self.faces.iter().map(|f| f.0).min().unwrap(), self.faces.iter().map(|f| f.0).max().unwrap());
+		let (b0, b1) = (self.faces.iter().map(|f| f.1).min().unwrap(), self.faces.iter().map(|f| f.1).max().unwrap());
+		(a1 - a0, b1 - b0)
+	}
+
+	// The first Betti number of the curve as a graph, that is, the number of its bounded faces.
+	pub fn genus(&self) -> usize {
+		let mut parent: Vec<_> = (0..self.vertices.len()).collect();
+		fn root(parent: &mut [usize], mut v: usize) -> usize {
+			while parent[v] != v {
+				parent[v] = parent[parent[v]];
+				v = parent[v];
+			}
+			v
+		}
+		let mut components = self.vertices.len();
+		for e in &self.edges {
+			let (r0, r1) = (root(&mut parent, e.ends.0), root(&mut parent, e.ends.1));
+			if r0 != r1 {
+				parent[r0] = r1;
+				components -= 1;
+			}
+		}
+		self.edges.len() + components - self.vertices.len()
+	}
+
+	// The frame of the sink and the edges, their widths growing with the weights, one unit being a cell of `scale` pixels.
+	pub fn svg(&self, fname: &str, scale: usize) -> io::Result<()> {
+		let (x, y) = self.size;
+		let mut s = format!("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"-1.5 -1.5 {} {}\">\n",
+			(x + 2) * scale, (y + 2) * scale, x + 2, y + 2);
+		s += &format!("<rect x=\"-1\" y=\"-1\" width=\"{}\" height=\"{}\" fill=\"white\" stroke=\"grey\" stroke-width=\"0.1\"/>\n", x + 1, y + 1);
+		let coordinate = |p: i128, q: i128| p as f64 / q as f64;
+		for e in &self.edges {
+			let (p, q) = (self.vertices[e.ends.0], self.vertices[e.ends.1]);
+			s += &format!("<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"black\" stroke-width=\"{}\" stroke-linecap=\"round\"/>\n",
+				coordinate(p.x, p.q), coordinate(p.y, p.q), coordinate(q.x, q.q), coordinate(q.y, q.q), 0.2 * e.weight as f64);
+		}
+		s += "</svg>\n";
+		fs::write(fname, s)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn tropical_curve() {
+		// The README example: the odometer is 2x + 2 near the left side and 40 - x near the right one, and there are four bounded faces.
+		let mut g = vec![vec![3; 40]; 40];
+		for (x, y) in [(2, 6), (8, 36), (12, 13), (17, 10)] {
+			g[y][x] += 1;
+		}
+		let s = GridSandpile::from_grid(GridType::Finite(FiniteGridType::Rectangular), Neighbourhood::VonNeumann, g).unwrap();
+		let curve = s.tropical_curve().unwrap();
+		assert_eq!(curve.faces().len(), 8);
+		assert!(curve.faces().contains(&(2, 0, 2)) && curve.faces().contains(&(-1, 0, 40)));
+		assert_eq!(curve.degree(), (3, 4));
+		assert_eq!(curve.genus(), 4);
+		// Two corners of the grid, where the slopes 2 meet, have edges of weight 2.
+		assert_eq!(curve.edges().iter().filter(|e| e.weight == 2).count(), 2);
+		assert!(curve.edges().iter().all(|e| e.weight <= 2));
+		// Every vertex inside the grid has its edges balanced, and the others are on the frame.
+		for (v, p) in curve.vertices().iter().enumerate() {
+			if p.x == -p.q || p.y == -p.q || p.x == 40 * p.q || p.y == 40 * p.q {
+				continue;
+			}
+			let mut sum = (0, 0);
+			for e in curve.edges() {
+				let (dx, dy) = e.direction;
+				if e.ends.0 == v {
+					sum = (sum.0 + dx * e.weight, sum.1 + dy * e.weight);
+				} else if e.ends.1 == v {
+					sum = (sum.0 - dx * e.weight, sum.1 - dy * e.weight);
+				}
+			}
+			assert_eq!(sum, (0, 0), "vertex {p}");
+		}
+		// Without topplings there is no curve.
+		let s = GridSandpile::from_grid(GridType::Finite(FiniteGridType::Rectangular), Neighbourhood::VonNeumann, vec![vec![3; 5]; 4]).unwrap();
+		let curve = s.tropical_curve().unwrap();
+		assert_eq!((curve.faces(), curve.edges().len(), curve.genus()), (&[(0, 0, 0)][..], 0, 0));
+		let s = GridSandpile::from_grid(GridType::Finite(FiniteGridType::Toroidal), Neighbourhood::VonNeumann, vec![vec![3; 5]; 4]).unwrap();
+		assert!(s.tropical_curve().is_err());
+	}
+}